@@ -0,0 +1,87 @@
+//! Tokenizer throughput on a synthetic `.nets` file.
+//!
+//! `cargo bench --bench tokenizer` writes a file with `BENCH_PINS` pins
+//! (default 4M) to the temp dir, then times raw tokenization and a full
+//! `Nets::read_from_file`.
+
+use std::{
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use bookshelf2lefdef::{
+    diagnostics::{Diagnostics, ParseMode},
+    io::reader::CommonReader,
+    nets::Nets,
+};
+
+const PINS_PER_NET: usize = 4;
+
+fn write_nets(path: &Path, num_pins: usize) -> std::io::Result<()> {
+    let mut out = BufWriter::new(std::fs::File::create(path)?);
+    let num_nets = num_pins / PINS_PER_NET;
+    writeln!(out, "UCLA nets 1.0")?;
+    writeln!(out, "# synthetic benchmark")?;
+    writeln!(out, "NumNets : {}", num_nets)?;
+    writeln!(out, "NumPins : {}", num_nets * PINS_PER_NET)?;
+    for net in 0..num_nets {
+        writeln!(out, "NetDegree : {} n{}", PINS_PER_NET, net)?;
+        for pin in 0..PINS_PER_NET {
+            let dir = if pin == 0 { "O" } else { "I" };
+            writeln!(
+                out,
+                "\to{} {} : {:.1} {:.1}",
+                (net * 7 + pin) % 1_000_000,
+                dir,
+                (pin as f64) * 0.5 - 1.0,
+                -(pin as f64) * 1.5
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn report(what: &str, bytes: u64, pins: usize, seconds: f64) {
+    println!(
+        "{:<12} {:>8.3} s {:>9.1} MB/s {:>7.2} Mpins/s",
+        what,
+        seconds,
+        bytes as f64 / 1e6 / seconds,
+        pins as f64 / 1e6 / seconds
+    );
+}
+
+fn main() -> anyhow::Result<()> {
+    let num_pins = std::env::var("BENCH_PINS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(4_000_000);
+    let path: PathBuf = std::env::temp_dir().join("bookshelf2lefdef_bench.nets");
+    write_nets(&path, num_pins)?;
+    let bytes = std::fs::metadata(&path)?.len();
+    println!(
+        "{}: {} pins, {:.1} MB",
+        path.display(),
+        num_pins,
+        bytes as f64 / 1e6
+    );
+
+    let start = Instant::now();
+    let mut reader = CommonReader::new_from_path(&path)?;
+    let mut num_tokens = 0usize;
+    while reader.next_token()?.is_some() {
+        num_tokens += 1;
+    }
+    report("tokenize", bytes, num_pins, start.elapsed().as_secs_f64());
+    println!("  {} tokens", num_tokens);
+
+    let start = Instant::now();
+    let mut diagnostics = Diagnostics::new(ParseMode::Strict);
+    let nets = Nets::read_from_file(path.clone(), &mut diagnostics)?;
+    report("Nets::read", bytes, num_pins, start.elapsed().as_secs_f64());
+    println!("  {} nets", nets.len());
+
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
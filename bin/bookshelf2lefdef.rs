@@ -1,13 +1,73 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use bookshelf2lefdef::{aux::Aux, io::logger::init_logger, lefdef, parser};
+use bookshelf2lefdef::{
+    aux::Aux,
+    diagnostics::ParseMode,
+    flavor::Flavor,
+    io::{logger::init_logger, source},
+    lefdef::{
+        self,
+        options::{LayerPolicy, PorosityStrategy},
+    },
+    parser,
+};
 use clap::Parser;
 use log::info;
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    /// The .aux file, or the .aux member name when `--archive` is given.
     #[arg(short, long)]
     input: String,
+    /// A (compressed) tarball holding the benchmark; files are read from it
+    /// without unpacking.
+    #[arg(long)]
+    archive: Option<PathBuf>,
+    /// Skip malformed records and report them all at the end instead of
+    /// stopping at the first one.
+    #[arg(long)]
+    lenient: bool,
+    /// Also write `.wts` weights on DEF COMPONENTS (net weights are always
+    /// written).
+    #[arg(long)]
+    component_weights: bool,
+    /// Benchmark flavor (ispd2005, ispd2006, ispd2011 or dac2012); detected
+    /// from the input when not given. ispd2011 and dac2012 need a .route
+    /// file unless reading with --lenient.
+    #[arg(long)]
+    flavor: Option<Flavor>,
+    /// How to write `.route` blockages with a nonzero BlockagePorosity: obs
+    /// (LEF OBS, fully blocked), partial-placement (DEF `+ PARTIAL`
+    /// placement blockages, routing left open) or striped (DEF routing
+    /// blockages leaving that share of tracks free).
+    #[arg(long, default_value_t = PorosityStrategy::Obs)]
+    porosity: PorosityStrategy,
+    /// Widen each layer's track pitch so that a routing tile holds as many
+    /// tracks as the .route capacity, instead of width plus spacing.
+    #[arg(long)]
+    capacity_pitch: bool,
+    /// Direction of .route layers with capacity in both directions or in
+    /// neither: dominant (more capacity wins), alternate (opposite to the
+    /// layer below) or pin-only (alternate, without tracks).
+    #[arg(long, default_value_t = LayerPolicy::Dominant)]
+    layer_policy: LayerPolicy,
+    /// Edge of the square pin shapes of generated cells, in microns;
+    /// defaults to the min wire width of the pin layer.
+    #[arg(long)]
+    pin_size: Option<f64>,
+    /// Move each pin shape of the generated cells to the nearest track
+    /// crossing of its layer inside the cell, keeping pins apart. Crossings
+    /// are taken for a cell on the site grid of the first row, so only
+    /// layers whose pitch divides the site width and row height are snapped.
+    #[arg(long)]
+    snap_pins: bool,
+    /// Routing layers to make up for a benchmark without .route (ISPD
+    /// 2005/2006).
+    #[arg(long, default_value_t = 4)]
+    default_layers: usize,
+    /// Pitch of the made-up routing layers, in site widths.
+    #[arg(long, default_value_t = 1.0)]
+    default_pitch: f64,
 }
 
 #[derive(Debug)]
@@ -18,7 +78,15 @@ struct OutPaths {
 }
 
 impl OutPaths {
-    pub fn build(aux_path: &PathBuf) -> Self {
+    pub fn build(aux_path: &Path) -> Self {
+        // outputs of an archived benchmark go next to the archive
+        let aux_path = match source::split_archive_path(aux_path) {
+            Some((archive, member)) => archive
+                .parent()
+                .unwrap_or(Path::new(""))
+                .join(member.file_name().unwrap_or_default()),
+            None => aux_path.to_path_buf(),
+        };
         let mut techlef = aux_path.clone();
         techlef.set_extension("tech.lef");
 
@@ -36,18 +104,49 @@ impl OutPaths {
     }
 }
 #[tokio::main]
-pub async fn main() {
+pub async fn main() -> anyhow::Result<()> {
     init_logger();
     let args = Args::parse();
-    let aux_path = PathBuf::from(args.input);
-    let aux = Aux::build(&aux_path).await.unwrap();
-    let bookshelf = parser::Bookshelf::build_from_aux(aux).await.unwrap();
-    let techlef = lefdef::techlef::TechLef::build(&bookshelf).await.unwrap();
+    let aux_path = match args.archive {
+        Some(archive) => archive.join(args.input),
+        None => PathBuf::from(args.input),
+    };
+    let aux = Aux::build(&aux_path).await?;
+    let mode = if args.lenient {
+        ParseMode::Lenient
+    } else {
+        ParseMode::Strict
+    };
+    let options = lefdef::options::Options {
+        component_weights: args.component_weights,
+        porosity: args.porosity,
+        capacity_pitch: args.capacity_pitch,
+        layer_policy: args.layer_policy,
+        pin_size: args.pin_size,
+        snap_pins: args.snap_pins,
+    };
+    let read_options = parser::ReadOptions {
+        mode,
+        flavor: args.flavor,
+        default_layers: args.default_layers,
+        default_pitch: args.default_pitch,
+    };
+    let bookshelf = parser::Bookshelf::build_from_aux(aux, &read_options).await?;
+    if !bookshelf.nets.is_empty() {
+        let weighted = if bookshelf.wts.is_empty() {
+            ""
+        } else {
+            " (weighted by .wts)"
+        };
+        info!("HPWL{}: {:.3}", weighted, bookshelf.hpwl());
+    }
+    let techlef = lefdef::techlef::TechLef::build(&bookshelf, &options).await?;
     let out_paths = OutPaths::build(&aux_path);
     techlef.write_to_file(&out_paths.techlef).await;
-    let lef = lefdef::lef::Lef::build(&bookshelf).await.unwrap();
-    lef.write(&out_paths.lef).await.unwrap();
-    let def = lefdef::def::Def::build(&bookshelf, &lef);
-    def.write_to_file(&out_paths.def).unwrap();
+    let lef = lefdef::lef::Lef::build(&bookshelf, &techlef, &options).await?;
+    lef.write(&out_paths.lef).await?;
+    let def = lefdef::def::Def::build(&bookshelf, &techlef, &lef, &options)?;
+    def.write_to_file(&out_paths.def)?;
     info!("Wrote output to: {:?}", out_paths);
+    Ok(())
 }
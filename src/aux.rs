@@ -1,10 +1,15 @@
-use std::{fmt, path::PathBuf};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
 
-use crate::io::reader::TokenReader;
+use crate::io::{reader::TokenReader, source};
 
 #[derive(Default, Debug)]
 pub struct Aux {
     pub me: Option<PathBuf>,
+    /// The keyword before `:`, `RowBasedPlacement` for the contest benchmarks.
+    pub header: Option<String>,
     pub nodes: Option<PathBuf>,
     pub nets: Option<PathBuf>,
     pub wts: Option<PathBuf>,
@@ -17,6 +22,7 @@ pub struct Aux {
 impl fmt::Display for Aux {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "Aux: {:?}", self.me.as_ref().map(|x| x.display()))?;
+        writeln!(f, "Header: {:?}", self.header)?;
         writeln!(
             f,
             "Node file: {:?}",
@@ -40,17 +46,22 @@ impl fmt::Display for Aux {
     }
 }
 impl Aux {
-    pub async fn build(aux_path: &PathBuf) -> anyhow::Result<Self> {
-        let mut res = Aux::default();
-        res.me = Some(aux_path.clone());
-        let mut reader = TokenReader::new_from_path(&aux_path);
+    pub async fn build(aux_path: &Path) -> anyhow::Result<Self> {
+        let mut res = Aux {
+            me: Some(aux_path.to_path_buf()),
+            ..Default::default()
+        };
+        let mut reader = TokenReader::new_from_path(aux_path)?;
         while let Some(token) = reader.next_token()? {
-            if token.as_bytes() == b":" {
+            if token == b":" {
                 break;
             }
+            if res.header.is_none() {
+                res.header = Some(String::from_utf8_lossy(token).into_owned());
+            }
         }
         while let Some(token) = reader.next_token()? {
-            let raw_file_path = PathBuf::from(token);
+            let raw_file_path = PathBuf::from(String::from_utf8_lossy(token).as_ref());
             let file_path = if raw_file_path.is_relative() {
                 res.me
                     .as_ref()
@@ -61,7 +72,7 @@ impl Aux {
             } else {
                 raw_file_path
             };
-            match file_path.extension() {
+            match source::format_extension(&file_path) {
                 Some(x) => match x.as_encoded_bytes() {
                     b"nets" => {
                         res.nets = Some(file_path);
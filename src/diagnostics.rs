@@ -0,0 +1,109 @@
+use std::fmt;
+
+/// How readers react to a malformed record.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Abort on the first malformed record.
+    #[default]
+    Strict,
+    /// Skip the malformed record, remember why, and keep going.
+    Lenient,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// A record was malformed and skipped.
+    Error,
+    /// The file parsed, but does not agree with itself, e.g. a header count
+    /// that does not match the records read.
+    Warning,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Problems collected while reading one or more bookshelf files.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    mode: ParseMode,
+    items: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new(mode: ParseMode) -> Self {
+        Self {
+            mode,
+            items: vec![],
+        }
+    }
+
+    pub fn mode(&self) -> ParseMode {
+        self.mode
+    }
+
+    /// Called by a reader when a record failed to parse.
+    /// Strict mode hands the error back; lenient mode records it so the
+    /// caller can skip the record and continue.
+    pub fn recover(&mut self, err: anyhow::Error) -> anyhow::Result<()> {
+        match self.mode {
+            ParseMode::Strict => Err(err),
+            ParseMode::Lenient => {
+                self.items.push(Diagnostic {
+                    severity: Severity::Error,
+                    message: format!("{:#}", err),
+                });
+                Ok(())
+            }
+        }
+    }
+
+    /// Record a warning. Warnings never abort, in either mode.
+    pub fn warn(&mut self, message: String) {
+        self.items.push(Diagnostic {
+            severity: Severity::Warning,
+            message,
+        });
+    }
+
+    /// Warn when a count declared in a header (`what`, e.g. `NumNodes`, read
+    /// at `location`) does not match the number of records actually read.
+    /// Skipped records and truncated files both show up here.
+    pub fn check_count(&mut self, location: &str, what: &str, declared: usize, read: usize) {
+        if declared != read {
+            self.warn(format!(
+                "{}: {} declares {}, but {} were read",
+                location, what, declared, read
+            ));
+        }
+    }
+
+    /// Move the diagnostics of `other` behind ours.
+    pub fn append(&mut self, other: Diagnostics) {
+        self.items.extend(other.items);
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+        self.items.iter()
+    }
+
+    pub fn into_vec(self) -> Vec<Diagnostic> {
+        self.items
+    }
+}
@@ -0,0 +1,224 @@
+use std::{fmt, str::FromStr};
+
+use crate::{aux::Aux, nodes::Nodes};
+
+/// Which contest a bookshelf benchmark comes from. The formats are close but
+/// not equal: ISPD 2005/2006 have no `.route`/`.shapes` and mark every fixed
+/// object, pads included, as `terminal`; ISPD 2011 and DAC 2012 add routing
+/// resources and `terminal_NI` IO pins that may overlap placed cells.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Flavor {
+    Ispd2005,
+    Ispd2006,
+    Ispd2011,
+    Dac2012,
+    #[default]
+    Unknown,
+}
+
+/// Benchmark names of each contest. superblue2/12 were used by both ISPD
+/// 2011 and DAC 2012 and resolve to the former, which comes first.
+const KNOWN_BENCHMARKS: [(Flavor, &[&str]); 4] = [
+    (
+        Flavor::Ispd2005,
+        &[
+            "adaptec1", "adaptec2", "adaptec3", "adaptec4", "bigblue1", "bigblue2", "bigblue3",
+            "bigblue4",
+        ],
+    ),
+    (
+        Flavor::Ispd2006,
+        &[
+            "adaptec5", "newblue1", "newblue2", "newblue3", "newblue4", "newblue5", "newblue6",
+            "newblue7",
+        ],
+    ),
+    (
+        Flavor::Ispd2011,
+        &[
+            "superblue1",
+            "superblue2",
+            "superblue4",
+            "superblue5",
+            "superblue10",
+            "superblue12",
+            "superblue15",
+            "superblue18",
+        ],
+    ),
+    (
+        Flavor::Dac2012,
+        &[
+            "superblue3",
+            "superblue6",
+            "superblue7",
+            "superblue9",
+            "superblue11",
+            "superblue14",
+            "superblue16",
+            "superblue19",
+        ],
+    ),
+];
+
+impl Flavor {
+    /// Flavors that come with `.route`/`.shapes` and `terminal_NI` pins, i.e.
+    /// with the routing resources the technology LEF is built from.
+    pub fn is_routability(&self) -> bool {
+        matches!(self, Flavor::Ispd2011 | Flavor::Dac2012)
+    }
+
+    /// Look the benchmark up by the name of its aux file, e.g. `superblue1`.
+    fn from_benchmark_name(aux: &Aux) -> Option<Self> {
+        let name = aux.me.as_ref()?.file_stem()?.to_string_lossy();
+        let name = name.split('.').next()?.to_ascii_lowercase();
+        KNOWN_BENCHMARKS
+            .iter()
+            .find(|(_, names)| names.contains(&name.as_str()))
+            .map(|(flavor, _)| *flavor)
+    }
+
+    /// Guess the flavor from the aux header, the files listed in it, the
+    /// node contents and, to tell contests with the same format apart, the
+    /// benchmark name. Returns the flavor and why it was picked.
+    pub fn detect(aux: &Aux, nodes: &Nodes) -> (Self, String) {
+        let has_terminal_ni = nodes.iter().any(|x| nodes.is_terminal_ni(&x.name));
+        let routability = aux.route.is_some() || aux.shapes.is_some() || has_terminal_ni;
+        let files = if routability {
+            "has .route/.shapes/terminal_NI"
+        } else {
+            "no .route/.shapes/terminal_NI"
+        };
+        match Self::from_benchmark_name(aux) {
+            Some(flavor) if flavor.is_routability() == routability => {
+                (flavor, format!("benchmark name, {}", files))
+            }
+            Some(flavor) => {
+                let by_files = Self::detect_by_files(aux, routability);
+                let reason = format!(
+                    "{}; the benchmark name suggests {}, which does not match",
+                    files, flavor
+                );
+                (by_files, reason)
+            }
+            None => (Self::detect_by_files(aux, routability), files.to_string()),
+        }
+    }
+
+    /// Without a known benchmark name, pick the contest that defined the
+    /// format: ISPD 2011 for routability benchmarks, ISPD 2005 otherwise.
+    fn detect_by_files(aux: &Aux, routability: bool) -> Self {
+        let is_placement = aux
+            .header
+            .as_deref()
+            .is_some_and(|x| x.eq_ignore_ascii_case("RowBasedPlacement"));
+        if routability {
+            Flavor::Ispd2011
+        } else if is_placement && aux.nodes.is_some() && aux.pl.is_some() {
+            Flavor::Ispd2005
+        } else {
+            Flavor::Unknown
+        }
+    }
+}
+
+impl fmt::Display for Flavor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Flavor::Ispd2005 => "ISPD2005",
+            Flavor::Ispd2006 => "ISPD2006",
+            Flavor::Ispd2011 => "ISPD2011",
+            Flavor::Dac2012 => "DAC2012",
+            Flavor::Unknown => "unknown",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Flavor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ispd2005" => Ok(Flavor::Ispd2005),
+            "ispd2006" => Ok(Flavor::Ispd2006),
+            "ispd2011" => Ok(Flavor::Ispd2011),
+            "dac2012" => Ok(Flavor::Dac2012),
+            _ => Err(format!(
+                "unknown flavor `{}`, expected ispd2005, ispd2006, ispd2011 or dac2012",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::nodes::{Movable, Node};
+
+    fn aux(name: &str, routability: bool) -> Aux {
+        let file = |ext: &str| Some(PathBuf::from(format!("{}.{}", name, ext)));
+        Aux {
+            me: file("aux"),
+            header: Some("RowBasedPlacement".to_string()),
+            nodes: file("nodes"),
+            pl: file("pl"),
+            route: if routability { file("route") } else { None },
+            ..Default::default()
+        }
+    }
+
+    fn nodes_with_terminal_ni() -> Nodes {
+        let mut nodes = Nodes::default();
+        let node = Node {
+            name: "p".to_string(),
+            moveable: Movable::FixedButOverlapAllowed,
+            ..Default::default()
+        };
+        nodes.nodes.insert(node.name.clone(), node);
+        nodes
+    }
+
+    #[test]
+    fn detects_known_benchmarks_by_name() {
+        let nodes = Nodes::default();
+        let detect = |name, routability| Flavor::detect(&aux(name, routability), &nodes).0;
+        assert_eq!(detect("adaptec1", false), Flavor::Ispd2005);
+        assert_eq!(detect("newblue1", false), Flavor::Ispd2006);
+        assert_eq!(detect("superblue1", true), Flavor::Ispd2011);
+        assert_eq!(detect("superblue12", true), Flavor::Ispd2011);
+        assert_eq!(detect("SUPERBLUE3", true), Flavor::Dac2012);
+    }
+
+    #[test]
+    fn files_win_over_a_mismatching_name() {
+        let (flavor, reason) = Flavor::detect(&aux("superblue1", false), &Nodes::default());
+        assert_eq!(flavor, Flavor::Ispd2005);
+        assert!(reason.contains("suggests ISPD2011"));
+        let (flavor, _) = Flavor::detect(&aux("adaptec1", false), &nodes_with_terminal_ni());
+        assert_eq!(flavor, Flavor::Ispd2011);
+    }
+
+    #[test]
+    fn unknown_names_fall_back_to_the_files() {
+        let nodes = Nodes::default();
+        assert_eq!(
+            Flavor::detect(&aux("chip", true), &nodes).0,
+            Flavor::Ispd2011
+        );
+        assert_eq!(
+            Flavor::detect(&aux("chip", false), &nodes).0,
+            Flavor::Ispd2005
+        );
+        assert_eq!(Flavor::detect(&Aux::default(), &nodes).0, Flavor::Unknown);
+    }
+
+    #[test]
+    fn parses_names_case_insensitively() {
+        assert_eq!("DAC2012".parse::<Flavor>(), Ok(Flavor::Dac2012));
+        assert!("ispd2015".parse::<Flavor>().is_err());
+    }
+}
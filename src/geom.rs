@@ -1,6 +1,6 @@
-use std::{fs::File, io::BufReader, ops::AddAssign};
+use std::{fmt, ops::AddAssign};
 
-use crate::io::reader::{CommonReader, TokenReader};
+use crate::io::reader::{CommonReader, FromToken, Keyword};
 
 #[derive(Debug, Default, Clone, Copy, PartialEq)]
 pub struct Point {
@@ -16,25 +16,320 @@ impl AddAssign for Point {
 }
 
 impl Point {
-    pub async fn read(reader: &mut TokenReader<BufReader<File>>) -> anyhow::Result<Self> {
-        let mut res = Self::default();
-        res.x = str::parse::<f64>(reader.next_token()?.unwrap()).unwrap();
-        res.y = str::parse::<f64>(reader.next_token()?.unwrap()).unwrap();
-        Ok(res)
+    pub fn read(reader: &mut CommonReader) -> anyhow::Result<Self> {
+        let x = reader.next_parse::<f64>("x coordinate")?;
+        let y = reader.next_parse::<f64>("y coordinate")?;
+        Ok(Self { x, y })
     }
 }
 
-#[derive(Debug, Default)]
+/// Placement orientation, named as in `.pl` and DEF.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Orient {
+    /// R0
+    #[default]
+    N,
+    /// R90
+    W,
+    /// R180
+    S,
+    /// R270
+    E,
+    /// Mirrored about the y axis
+    FN,
+    /// Mirrored about the y axis, then R90
+    FE,
+    /// Mirrored about the x axis
+    FS,
+    /// Mirrored about the x axis, then R90
+    FW,
+}
+
+impl Orient {
+    /// Whether width and height are swapped.
+    pub fn is_rotated(&self) -> bool {
+        matches!(self, Orient::W | Orient::E | Orient::FE | Orient::FW)
+    }
+
+    /// Whether the cell is mirrored, with or without a rotation.
+    pub fn is_mirrored(&self) -> bool {
+        matches!(self, Orient::FN | Orient::FE | Orient::FS | Orient::FW)
+    }
+
+    /// Whether the cell is flipped about the x axis (or, rotated, turned into
+    /// such a flip); placing it needs SYMMETRY X.
+    pub fn needs_symmetry_x(&self) -> bool {
+        matches!(self, Orient::S | Orient::FS | Orient::FW)
+    }
+
+    /// Whether placing the cell needs SYMMETRY Y.
+    pub fn needs_symmetry_y(&self) -> bool {
+        matches!(self, Orient::S | Orient::FN | Orient::FE)
+    }
+
+    /// The orientation that undoes this one.
+    pub fn inverse(&self) -> Self {
+        match self {
+            Orient::W => Orient::E,
+            Orient::E => Orient::W,
+            x => *x,
+        }
+    }
+
+    /// Move `offset`, relative to the cell center, to where it ends up once
+    /// the cell is placed in this orientation.
+    pub fn apply(&self, offset: Point) -> Point {
+        let Point { x, y } = offset;
+        let (x, y) = match self {
+            Orient::N => (x, y),
+            Orient::W => (-y, x),
+            Orient::S => (-x, -y),
+            Orient::E => (y, -x),
+            Orient::FN => (-x, y),
+            Orient::FE => (-y, -x),
+            Orient::FS => (x, -y),
+            Orient::FW => (y, x),
+        };
+        Point { x, y }
+    }
+}
+
+impl FromToken for Orient {
+    fn from_token(token: &[u8]) -> Option<Self> {
+        match Keyword::new(token).as_bytes() {
+            b"N" => Some(Orient::N),
+            b"W" => Some(Orient::W),
+            b"S" => Some(Orient::S),
+            b"E" => Some(Orient::E),
+            b"FN" => Some(Orient::FN),
+            b"FE" => Some(Orient::FE),
+            b"FS" => Some(Orient::FS),
+            b"FW" => Some(Orient::FW),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Orient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// `SYMMETRY` of a LEF macro or site (and `Sitesymmetry` in `.scl`): the
+/// orientations it may be placed in.
+#[derive(Debug, Clone, Copy)]
+pub struct Symmetry {
+    pub x: bool,
+    pub y: bool,
+    pub r90: bool,
+}
+
+impl Default for Symmetry {
+    /// Row-based cells may flip but not rotate.
+    fn default() -> Self {
+        Self {
+            x: true,
+            y: true,
+            r90: false,
+        }
+    }
+}
+
+impl Symmetry {
+    pub const NONE: Self = Self {
+        x: false,
+        y: false,
+        r90: false,
+    };
+
+    /// Add `X`, `Y` or `R90`. Returns false for any other token.
+    pub fn add_token(&mut self, token: &[u8]) -> bool {
+        match Keyword::new(token).as_bytes() {
+            b"X" => self.x = true,
+            b"Y" => self.y = true,
+            b"R90" => self.r90 = true,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Whether a macro may be placed in `orient`: the orientations the
+    /// symmetries make together. `R90` alone turns the cell all the way
+    /// round (N, W, S, E); with a flip on top it reaches every orientation.
+    pub fn allows(&self, orient: Orient) -> bool {
+        if self.r90 {
+            return self.x || self.y || !orient.is_mirrored();
+        }
+        !orient.is_rotated()
+            && (self.x || !orient.needs_symmetry_x())
+            && (self.y || !orient.needs_symmetry_y())
+    }
+}
+
+impl fmt::Display for Symmetry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [(self.x, "X"), (self.y, "Y"), (self.r90, "R90")];
+        let names: Vec<&str> = names.iter().filter(|x| x.0).map(|x| x.1).collect();
+        write!(f, "{}", names.join(" "))
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
 pub struct Rect {
     pub ll: Point,
     pub ur: Point,
 }
 
 impl Rect {
-    pub async fn read_by_lowerleft_width_height(reader: &mut CommonReader) -> anyhow::Result<Self> {
-        let ll = Point::read(reader).await?;
-        let mut ur = Point::read(reader).await?;
+    pub fn width(&self) -> f64 {
+        self.ur.x - self.ll.x
+    }
+
+    pub fn height(&self) -> f64 {
+        self.ur.y - self.ll.y
+    }
+
+    pub fn center(&self) -> Point {
+        Point {
+            x: (self.ll.x + self.ur.x) / 2.0,
+            y: (self.ll.y + self.ur.y) / 2.0,
+        }
+    }
+
+    /// Whether the interiors meet; touching edges do not count.
+    pub fn overlaps(&self, other: &Rect) -> bool {
+        self.ll.x < other.ur.x
+            && other.ll.x < self.ur.x
+            && self.ll.y < other.ur.y
+            && other.ll.y < self.ur.y
+    }
+
+    /// The bounding box of both.
+    pub fn union(&self, other: &Rect) -> Self {
+        Rect {
+            ll: Point {
+                x: self.ll.x.min(other.ll.x),
+                y: self.ll.y.min(other.ll.y),
+            },
+            ur: Point {
+                x: self.ur.x.max(other.ur.x),
+                y: self.ur.y.max(other.ur.y),
+            },
+        }
+    }
+
+    /// Map both corners through `f` and normalize.
+    pub fn map_corners(&self, f: impl Fn(Point) -> Point) -> Self {
+        let (a, b) = (f(self.ll), f(self.ur));
+        Rect {
+            ll: Point {
+                x: a.x.min(b.x),
+                y: a.y.min(b.y),
+            },
+            ur: Point {
+                x: a.x.max(b.x),
+                y: a.y.max(b.y),
+            },
+        }
+    }
+
+    pub fn read_by_lowerleft_width_height(reader: &mut CommonReader) -> anyhow::Result<Self> {
+        let ll = Point::read(reader)?;
+        let mut ur = Point::read(reader)?;
         ur += ll;
         Ok(Self { ll, ur })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Orient; 8] = [
+        Orient::N,
+        Orient::W,
+        Orient::S,
+        Orient::E,
+        Orient::FN,
+        Orient::FE,
+        Orient::FS,
+        Orient::FW,
+    ];
+
+    fn allowed(symmetry: Symmetry) -> Vec<Orient> {
+        ALL.into_iter().filter(|x| symmetry.allows(*x)).collect()
+    }
+
+    #[test]
+    fn orientations_move_offsets() {
+        let offset = Point { x: 2.0, y: 1.0 };
+        let moved: Vec<(f64, f64)> = ALL
+            .iter()
+            .map(|x| x.apply(offset))
+            .map(|p| (p.x, p.y))
+            .collect();
+        assert_eq!(
+            moved,
+            [
+                (2.0, 1.0),
+                (-1.0, 2.0),
+                (-2.0, -1.0),
+                (1.0, -2.0),
+                (-2.0, 1.0),
+                (-1.0, -2.0),
+                (2.0, -1.0),
+                (1.0, 2.0)
+            ]
+        );
+        for orient in ALL {
+            assert_eq!(orient.inverse().apply(orient.apply(offset)), offset);
+        }
+    }
+
+    #[test]
+    fn orientations_parse_and_print() {
+        for orient in ALL {
+            let name = orient.to_string();
+            assert_eq!(Orient::from_token(name.as_bytes()), Some(orient));
+            let lower = name.to_ascii_lowercase();
+            assert_eq!(Orient::from_token(lower.as_bytes()), Some(orient));
+        }
+        assert_eq!(Orient::from_token(b"R90"), None);
+    }
+
+    #[test]
+    fn symmetry_allows_orientations() {
+        use Orient::*;
+        assert_eq!(allowed(Symmetry::NONE), [N]);
+        assert_eq!(allowed(Symmetry::default()), [N, S, FN, FS]);
+        let y = Symmetry {
+            y: true,
+            ..Symmetry::NONE
+        };
+        assert_eq!(allowed(y), [N, FN]);
+        let x = Symmetry {
+            x: true,
+            ..Symmetry::NONE
+        };
+        assert_eq!(allowed(x), [N, FS]);
+        let r90 = Symmetry {
+            r90: true,
+            ..Symmetry::NONE
+        };
+        assert_eq!(allowed(r90), [N, W, S, E]);
+        for flip in [x, y, Symmetry::default()] {
+            let symmetry = Symmetry { r90: true, ..flip };
+            assert_eq!(allowed(symmetry), ALL, "{symmetry}");
+        }
+    }
+
+    #[test]
+    fn symmetry_reads_tokens() {
+        let mut symmetry = Symmetry::NONE;
+        assert!(symmetry.add_token(b"y"));
+        assert!(symmetry.add_token(b"R90"));
+        assert!(!symmetry.add_token(b"Z"));
+        assert_eq!(symmetry.to_string(), "Y R90");
+    }
+}
@@ -0,0 +1,36 @@
+use std::{fmt, path::PathBuf};
+
+/// A malformed token in a bookshelf file.
+///
+/// `line` and `column` are 1-based and point to the offending token,
+/// `found` is `None` when the record's line or the file ended early,
+/// `end_of_line` tells which.
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub path: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub expected: String,
+    pub found: Option<String>,
+    pub end_of_line: bool,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: expected {}, found ",
+            self.path.display(),
+            self.line,
+            self.column,
+            self.expected
+        )?;
+        match self.found.as_ref() {
+            Some(found) => write!(f, "`{}`", found),
+            None if self.end_of_line => write!(f, "end of line"),
+            None => write!(f, "end of file"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
@@ -1,2 +1,6 @@
+pub mod error;
 pub mod logger;
 pub mod reader;
+pub mod source;
+#[cfg(test)]
+pub(crate) mod test_util;
@@ -1,98 +1,653 @@
 use std::{
-    fs::File,
-    io::{self, BufRead, BufReader, Read},
-    path::PathBuf,
+    io::Read,
+    path::{Path, PathBuf},
 };
 
-pub type CommonReader = TokenReader<BufReader<File>>;
+use crate::{
+    diagnostics::Diagnostics,
+    io::{
+        error::ParseError,
+        source::{self, Source},
+    },
+};
+
+const BUFFER_SIZE: usize = 1 << 20;
+const KEYWORD_LEN: usize = 32;
+
+pub type CommonReader = TokenReader<Source>;
+
+/// Whitespace separated tokens over a large read buffer.
+///
+/// Tokens are handed out as `&[u8]` borrowed from the buffer, so scanning
+/// a file allocates nothing but the buffer itself. `#` comments and the
+/// `UCLA <format> 1.0` header line are skipped.
 pub struct TokenReader<R: Read> {
-    source: BufReader<R>,
+    source: R,
+    path: PathBuf,
     buffer: Vec<u8>,
+    /// `buffer[current_pos..filled]` is not consumed yet.
     current_pos: usize,
-    total_bytes_read: usize,
+    filled: usize,
+    eof: bool,
+    /// Offset of `buffer[0]` in the file.
+    buffer_offset: usize,
+    /// Offset of the first byte of the current line in the file.
+    line_start: usize,
+    line_has_token: bool,
+    /// Set by [`Self::read_line`]: tokens end at the current line.
+    on_line: bool,
+    /// Offset of the last token handed out in the file.
+    token_offset: usize,
+    line: usize,
+}
+
+impl TokenReader<Source> {
+    /// Open a bookshelf file, possibly compressed or inside an archive,
+    /// see [`source::open`].
+    pub fn new_from_path(file_path: &Path) -> anyhow::Result<Self> {
+        Ok(TokenReader::new(source::open(file_path)?).with_path(file_path.to_path_buf()))
+    }
+}
+
+/// A token upper-cased on the stack, for allocation-free keyword matching.
+/// Tokens longer than any keyword compare equal to nothing.
+pub struct Keyword {
+    bytes: [u8; KEYWORD_LEN],
+    len: usize,
 }
 
-impl TokenReader<BufReader<File>> {
-    pub fn new_from_path(file_path: &PathBuf) -> Self {
-        TokenReader::new(std::io::BufReader::new(
-            File::open(file_path.clone()).unwrap(),
-        ))
+impl Keyword {
+    pub fn new(token: &[u8]) -> Self {
+        let mut bytes = [0u8; KEYWORD_LEN];
+        let len = if token.len() <= KEYWORD_LEN {
+            for (to, from) in bytes.iter_mut().zip(token) {
+                *to = from.to_ascii_uppercase();
+            }
+            token.len()
+        } else {
+            0
+        };
+        Self { bytes, len }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+/// Values that can be parsed straight from a token.
+pub trait FromToken: Sized {
+    fn from_token(token: &[u8]) -> Option<Self>;
+}
+
+fn parse_with_std<T: std::str::FromStr>(token: &[u8]) -> Option<T> {
+    std::str::from_utf8(token).ok()?.parse().ok()
+}
+
+fn split_sign(token: &[u8]) -> Option<(bool, &[u8])> {
+    Some(match token.split_first()? {
+        (b'-', rest) => (true, rest),
+        (b'+', rest) => (false, rest),
+        _ => (false, token),
+    })
+}
+
+impl FromToken for i64 {
+    fn from_token(token: &[u8]) -> Option<Self> {
+        let (negative, digits) = split_sign(token)?;
+        if digits.is_empty() || digits.len() > 18 {
+            return parse_with_std(token);
+        }
+        let mut value: i64 = 0;
+        for x in digits {
+            if !x.is_ascii_digit() {
+                return None;
+            }
+            value = value * 10 + (x - b'0') as i64;
+        }
+        Some(if negative { -value } else { value })
+    }
+}
+
+impl FromToken for usize {
+    fn from_token(token: &[u8]) -> Option<Self> {
+        usize::try_from(i64::from_token(token)?).ok()
+    }
+}
+
+impl FromToken for f64 {
+    /// Plain decimals (`-12.5`) with at most 15 significant digits take an
+    /// exact fast path: both the mantissa and the power of ten are exact
+    /// doubles, so one division is correctly rounded. Anything else goes
+    /// through `str::parse`, keeping only finite values: `inf`, `NaN` and
+    /// overflowing exponents are no coordinates.
+    fn from_token(token: &[u8]) -> Option<Self> {
+        let parse_finite = || parse_with_std(token).filter(|x: &f64| x.is_finite());
+        const POW10: [f64; 16] = [
+            1e0, 1e1, 1e2, 1e3, 1e4, 1e5, 1e6, 1e7, 1e8, 1e9, 1e10, 1e11, 1e12, 1e13, 1e14, 1e15,
+        ];
+        let (negative, digits) = split_sign(token)?;
+        let mut mantissa: u64 = 0;
+        let mut num_digits = 0;
+        let mut fraction_digits = None;
+        for (i, x) in digits.iter().enumerate() {
+            match x {
+                b'0'..=b'9' => {
+                    mantissa = mantissa.wrapping_mul(10).wrapping_add((x - b'0') as u64);
+                    num_digits += 1;
+                }
+                b'.' if fraction_digits.is_none() => fraction_digits = Some(digits.len() - i - 1),
+                _ => return parse_finite(),
+            }
+        }
+        if num_digits == 0 || num_digits > 15 {
+            return parse_finite();
+        }
+        let value = mantissa as f64 / POW10[fraction_digits.unwrap_or(0)];
+        Some(if negative { -value } else { value })
     }
 }
 
 impl<R: Read> TokenReader<R> {
     pub fn new(source: R) -> Self {
         TokenReader {
-            source: BufReader::new(source),
-            buffer: Vec::new(),
+            source,
+            path: PathBuf::from("<stream>"),
+            buffer: vec![0; BUFFER_SIZE],
             current_pos: 0,
-            total_bytes_read: 0,
+            filled: 0,
+            eof: false,
+            buffer_offset: 0,
+            line_start: 0,
+            line_has_token: false,
+            on_line: false,
+            token_offset: 0,
+            line: 1,
         }
     }
 
-    pub fn swallow_line(&mut self) -> io::Result<usize> {
-        self.buffer.clear();
-        self.current_pos = 0;
-        let bytes_read = self.source.read_until(b'\n', &mut self.buffer)?;
-        self.total_bytes_read += bytes_read;
+    /// Name the source so errors can point to it.
+    pub fn with_path(mut self, path: PathBuf) -> Self {
+        self.path = path;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 1-based line of the last token handed out.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// 1-based column of the last token handed out.
+    pub fn column(&self) -> usize {
+        self.token_offset.saturating_sub(self.line_start) + 1
+    }
+
+    /// `path:line` of the last token handed out, for messages that are not
+    /// about a single token.
+    pub fn location(&self) -> String {
+        format!("{}:{}", self.path.display(), self.line)
+    }
+
+    pub fn total_bytes_read(&self) -> usize {
+        self.buffer_offset + self.current_pos
+    }
+
+    /// Move the unconsumed bytes to the front and read more behind them.
+    /// Returns the number of bytes read, 0 at end of file.
+    fn refill(&mut self) -> anyhow::Result<usize> {
+        if self.eof {
+            return Ok(0);
+        }
+        if self.current_pos > 0 {
+            self.buffer.copy_within(self.current_pos..self.filled, 0);
+            self.buffer_offset += self.current_pos;
+            self.filled -= self.current_pos;
+            self.current_pos = 0;
+        }
+        if self.filled == self.buffer.len() {
+            // a single token longer than the buffer
+            self.buffer.resize(self.buffer.len() * 2, 0);
+        }
+        let bytes_read = loop {
+            match self.source.read(&mut self.buffer[self.filled..]) {
+                Ok(x) => break x,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    return Err(anyhow::anyhow!(
+                        "failed to read {}: {}",
+                        self.path.display(),
+                        e
+                    ));
+                }
+            }
+        };
+        self.filled += bytes_read;
+        self.eof = bytes_read == 0;
         Ok(bytes_read)
     }
 
-    pub fn peek_token(&mut self) -> io::Result<Option<&str>> {
+    fn new_line(&mut self, newline_pos: usize) {
+        self.line += 1;
+        self.line_start = self.buffer_offset + newline_pos + 1;
+        self.line_has_token = false;
+    }
+
+    /// Skip the rest of the current line, including its newline.
+    /// Returns the number of bytes skipped, 0 at end of file.
+    pub fn swallow_line(&mut self) -> anyhow::Result<usize> {
+        let mut skipped = 0;
         loop {
-            while self.current_pos < self.buffer.len()
-                && self.buffer[self.current_pos].is_ascii_whitespace()
-            {
+            let rest = &self.buffer[self.current_pos..self.filled];
+            if let Some(i) = rest.iter().position(|x| *x == b'\n') {
+                let newline_pos = self.current_pos + i;
+                self.new_line(newline_pos);
+                self.current_pos = newline_pos + 1;
+                return Ok(skipped + i + 1);
+            }
+            skipped += rest.len();
+            self.current_pos = self.filled;
+            if self.refill()? == 0 {
+                return Ok(skipped);
+            }
+        }
+    }
+
+    /// Skip to the end of the current line, leaving its newline unread.
+    fn skip_to_newline(&mut self) -> anyhow::Result<()> {
+        loop {
+            let rest = &self.buffer[self.current_pos..self.filled];
+            if let Some(i) = rest.iter().position(|x| *x == b'\n') {
+                self.current_pos += i;
+                return Ok(());
+            }
+            self.current_pos = self.filled;
+            if self.refill()? == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Build an error located at the last token handed out (or peeked).
+    pub fn error(&self, expected: impl Into<String>, found: Option<&[u8]>) -> ParseError {
+        ParseError {
+            path: self.path.clone(),
+            line: self.line,
+            column: self.column(),
+            expected: expected.into(),
+            found: found.map(|x| String::from_utf8_lossy(x).into_owned()),
+            end_of_line: self.on_line,
+        }
+    }
+
+    /// Skip whitespace, comments and the `UCLA <format> 1.0` header, refilling
+    /// the buffer as needed, and locate the next token.
+    /// Returns its span in `buffer`, or `None` at end of file, or at end of
+    /// line inside [`Self::read_line`].
+    fn locate_token(&mut self) -> anyhow::Result<Option<(usize, usize)>> {
+        loop {
+            while self.current_pos < self.filled {
+                match self.buffer[self.current_pos] {
+                    b'\n' if self.on_line => return Ok(None),
+                    b'\n' => self.new_line(self.current_pos),
+                    x if x.is_ascii_whitespace() => {}
+                    _ => break,
+                }
                 self.current_pos += 1;
             }
-            if self.current_pos < self.buffer.len() {
-                let start = self.current_pos;
-                let mut end = start;
-                while end < self.buffer.len() && !self.buffer[end].is_ascii_whitespace() {
-                    end += 1;
+            if self.current_pos == self.filled {
+                if self.refill()? == 0 {
+                    return Ok(None);
                 }
-                let token_slice = &self.buffer[start..end];
-                let token_str = std::str::from_utf8(token_slice)
-                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-                return Ok(Some(token_str));
+                continue;
             }
-            let bytes_read = self.swallow_line()?;
-            if bytes_read == 0 {
-                return Ok(None);
+            let start = self.current_pos;
+            let end = match self.buffer[start..self.filled]
+                .iter()
+                .position(u8::is_ascii_whitespace)
+            {
+                Some(len) => start + len,
+                None if self.eof => self.filled,
+                None => {
+                    // the token may continue past the buffer
+                    self.refill()?;
+                    continue;
+                }
+            };
+            let token = &self.buffer[start..end];
+            let is_comment = token[0] == b'#';
+            let is_header = !self.line_has_token && token.eq_ignore_ascii_case(b"UCLA");
+            if is_comment || is_header {
+                // drop the rest of the line, but keep its newline for counting
+                // and for `read_line`
+                self.current_pos = end;
+                self.skip_to_newline()?;
+                continue;
             }
+            self.token_offset = self.buffer_offset + start;
+            self.line_has_token = true;
+            return Ok(Some((start, end)));
         }
     }
 
-    pub fn expect(&mut self, next_token: &str) -> anyhow::Result<()> {
-        assert_eq!(next_token, self.next_token()?.unwrap());
-        Ok(())
+    /// Run `read` on the line of the last token handed out or peeked, with
+    /// the end of that line seen as the end of input. A record cut short then
+    /// fails on its own line, so `swallow_line` after an error resumes at the
+    /// next record instead of dropping it too.
+    pub fn read_line<T>(
+        &mut self,
+        read: impl FnOnce(&mut Self) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let on_line = std::mem::replace(&mut self.on_line, true);
+        let res = read(self);
+        self.on_line = on_line;
+        res
     }
 
-    pub fn next_token(&mut self) -> io::Result<Option<&str>> {
+    /// Whether the next token is followed by `second` (ASCII
+    /// case-insensitive) on its line. Consumes nothing; for records that
+    /// only their second token tells apart, like `.shapes` headers.
+    pub fn peek_second_is(&mut self, second: &str) -> anyhow::Result<bool> {
         loop {
-            while self.current_pos < self.buffer.len()
-                && self.buffer[self.current_pos].is_ascii_whitespace()
+            let Some((_, end)) = self.locate_token()? else {
+                return Ok(false);
+            };
+            let rest = &self.buffer[end..self.filled];
+            if let Some(i) = rest
+                .iter()
+                .position(|x| !x.is_ascii_whitespace() || *x == b'\n')
             {
-                self.current_pos += 1;
+                if rest[i] == b'\n' {
+                    return Ok(false);
+                }
+                let token = &rest[i..];
+                match token.iter().position(u8::is_ascii_whitespace) {
+                    Some(len) => return Ok(token[..len].eq_ignore_ascii_case(second.as_bytes())),
+                    None if self.eof => return Ok(token.eq_ignore_ascii_case(second.as_bytes())),
+                    None => {}
+                }
+            } else if self.eof {
+                return Ok(false);
             }
+            // the second token may continue past the buffer; the first one
+            // starts at `current_pos`, so refilling keeps it
+            self.refill()?;
+        }
+    }
 
-            if self.current_pos < self.buffer.len() {
-                let start = self.current_pos;
-                let mut end = start;
-                while end < self.buffer.len() && !self.buffer[end].is_ascii_whitespace() {
-                    end += 1;
-                }
-                let token_slice = &self.buffer[start..end];
-                let token_str = std::str::from_utf8(token_slice)
-                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    pub fn peek_token(&mut self) -> anyhow::Result<Option<&[u8]>> {
+        match self.locate_token()? {
+            Some((start, end)) => Ok(Some(&self.buffer[start..end])),
+            None => Ok(None),
+        }
+    }
+
+    pub fn next_token(&mut self) -> anyhow::Result<Option<&[u8]>> {
+        match self.locate_token()? {
+            Some((start, end)) => {
+                self.current_pos = end;
+                Ok(Some(&self.buffer[start..end]))
+            }
+            None => Ok(None),
+        }
+    }
 
+    /// Next token, treating end of file as an error. `what` describes the
+    /// token for the error message.
+    pub fn next_bytes(&mut self, what: &str) -> anyhow::Result<&[u8]> {
+        match self.locate_token()? {
+            Some((start, end)) => {
                 self.current_pos = end;
-                return Ok(Some(token_str));
+                Ok(&self.buffer[start..end])
+            }
+            None => Err(self.error(what, None).into()),
+        }
+    }
+
+    /// Next token as an owned string, for names that are kept.
+    pub fn next_string(&mut self, what: &str) -> anyhow::Result<String> {
+        let token = self.next_bytes(what)?;
+        match std::str::from_utf8(token) {
+            Ok(x) => Ok(x.to_string()),
+            Err(_) => {
+                let found = token.to_vec();
+                Err(self.error(format!("{} (UTF-8)", what), Some(&found)).into())
+            }
+        }
+    }
+
+    /// Next token parsed as `T`.
+    pub fn next_parse<T: FromToken>(&mut self, what: &str) -> anyhow::Result<T> {
+        let token = self.next_bytes(what)?;
+        match T::from_token(token) {
+            Some(x) => Ok(x),
+            None => {
+                let found = token.to_vec();
+                Err(self.error(what, Some(&found)).into())
+            }
+        }
+    }
+
+    /// The `: <count>` following a header keyword such as `NumNodes`.
+    pub fn next_count(&mut self, what: &str) -> anyhow::Result<usize> {
+        self.expect(":")?;
+        self.next_parse(what)
+    }
+
+    /// [`Self::next_count`] for a header line. A malformed count is handed to
+    /// `diagnostics` like any malformed record, and the line is skipped.
+    pub fn header_count(
+        &mut self,
+        what: &str,
+        diagnostics: &mut Diagnostics,
+    ) -> anyhow::Result<Option<usize>> {
+        match self.next_count(what) {
+            Ok(count) => Ok(Some(count)),
+            Err(e) => {
+                diagnostics.recover(e)?;
+                self.swallow_line()?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Drop tokens until the next one is `keyword` (ASCII case-insensitive) or
+    /// the file ends. `keyword` itself is left in the stream.
+    pub fn skip_until(&mut self, keyword: &str) -> anyhow::Result<()> {
+        while let Some(token) = self.peek_token()? {
+            if token.eq_ignore_ascii_case(keyword.as_bytes()) {
+                break;
             }
-            let bytes_read = self.swallow_line()?;
-            if bytes_read == 0 {
-                return Ok(None);
+            self.next_token()?;
+        }
+        Ok(())
+    }
+
+    /// Consume the next token, which must be `next_token` (ASCII case-insensitive).
+    pub fn expect(&mut self, next_token: &str) -> anyhow::Result<()> {
+        match self.next_token()? {
+            Some(token) if token.eq_ignore_ascii_case(next_token.as_bytes()) => Ok(()),
+            found => {
+                let found = found.map(<[u8]>::to_vec);
+                Err(self
+                    .error(format!("`{}`", next_token), found.as_deref())
+                    .into())
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reader(text: &'static str) -> CommonReader {
+        TokenReader::new(Box::new(text.as_bytes()) as Source)
+    }
+
+    /// A source that hands out one byte per read, so every token and line
+    /// end crosses a refill.
+    struct Trickle(&'static [u8]);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = self.0.len().min(buf.len()).min(1);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    fn trickle(text: &'static str) -> CommonReader {
+        TokenReader::new(Box::new(Trickle(text.as_bytes())) as Source)
+    }
+
+    #[test]
+    fn integers_parse_like_std() {
+        for token in [
+            "0",
+            "-0",
+            "+7",
+            "-42",
+            "123456789012345678",
+            "-123456789012345678",
+            "9223372036854775807",
+            "-9223372036854775808",
+            "9223372036854775808",
+            "99999999999999999999",
+            "",
+            "-",
+            "+",
+            "1.0",
+            "1e3",
+            "--1",
+            "1-",
+        ] {
+            assert_eq!(
+                i64::from_token(token.as_bytes()),
+                token.parse().ok(),
+                "{token}"
+            );
+        }
+        assert_eq!(usize::from_token(b"-1"), None);
+    }
+
+    #[test]
+    fn floats_parse_like_std_but_finite() {
+        for token in [
+            "0",
+            "-0.0",
+            "+1.5",
+            "-12.5",
+            ".5",
+            "-.5",
+            "5.",
+            "0.1",
+            "123456789012345",
+            "1234567890.12345",
+            "1234567890123456",
+            "0.30000000000000004",
+            "1e3",
+            "-2.5E-3",
+            "1e308",
+            "4.9e-324",
+            "",
+            ".",
+            "+",
+            "-",
+            "1.2.3",
+            "1e",
+            "e5",
+            "0x10",
+        ] {
+            let expected: Option<f64> = token.parse().ok();
+            assert_eq!(
+                f64::from_token(token.as_bytes()).map(f64::to_bits),
+                expected.map(f64::to_bits),
+                "{token}"
+            );
+        }
+        for token in ["inf", "-inf", "+infinity", "NaN", "nan", "1e309", "-1e400"] {
+            assert_eq!(f64::from_token(token.as_bytes()), None, "{token}");
+        }
+    }
+
+    #[test]
+    fn tokens_skip_comments_and_header() {
+        let mut reader = reader("UCLA pl 1.0\n# comment\n  a 1\tb # c\n");
+        assert_eq!(reader.next_string("token").unwrap(), "a");
+        assert_eq!(reader.line(), 3);
+        assert_eq!(reader.column(), 3);
+        assert_eq!(reader.next_parse::<i64>("number").unwrap(), 1);
+        assert_eq!(reader.next_string("token").unwrap(), "b");
+        assert!(reader.next_token().unwrap().is_none());
+    }
+
+    #[test]
+    fn read_line_stops_at_line_end() {
+        let mut reader = reader("a 1\nb 2 3\n");
+        reader.next_token().unwrap();
+        let err = reader
+            .read_line(|reader| {
+                reader.next_parse::<i64>("x")?;
+                reader.next_parse::<i64>("y")
+            })
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .ends_with("1:3: expected y, found end of line")
+        );
+        reader.swallow_line().unwrap();
+        assert_eq!(reader.next_string("token").unwrap(), "b");
+        assert_eq!(reader.line(), 2);
+        let pair = reader.read_line(|reader| {
+            Ok((
+                reader.next_parse::<i64>("x")?,
+                reader.next_parse::<i64>("y")?,
+            ))
+        });
+        assert_eq!(pair.unwrap(), (2, 3));
+    }
+
+    #[test]
+    fn read_line_ignores_trailing_comment() {
+        // one byte per read puts the comment's newline past every refill
+        for mut reader in [reader("a # 1\n2\n"), trickle("a # 1\n2\n")] {
+            reader.next_token().unwrap();
+            assert!(
+                reader
+                    .read_line(|reader| reader.next_parse::<i64>("x"))
+                    .is_err()
+            );
+            reader.swallow_line().unwrap();
+            assert_eq!(reader.next_parse::<i64>("x").unwrap(), 2);
+        }
+    }
+
+    #[test]
+    fn comment_across_a_refill_keeps_its_newline() {
+        let mut reader = trickle("a # c\nB I : 1 2\n");
+        reader.next_token().unwrap();
+        let direction = reader.read_line(|reader| Ok(reader.peek_token()?.map(<[u8]>::to_vec)));
+        assert_eq!(direction.unwrap(), None);
+        assert_eq!(reader.next_string("token").unwrap(), "B");
+        assert_eq!(reader.line(), 2);
+    }
+
+    #[test]
+    fn second_token_is_peeked_across_refills() {
+        for mut reader in [reader("a : 2\nb 1\nc\n:"), trickle("a : 2\nb 1\nc\n:")] {
+            assert!(reader.peek_second_is(":").unwrap());
+            assert_eq!(reader.next_string("token").unwrap(), "a");
+            reader.swallow_line().unwrap();
+            assert!(!reader.peek_second_is(":").unwrap());
+            assert_eq!(reader.next_string("token").unwrap(), "b");
+            reader.swallow_line().unwrap();
+            assert!(!reader.peek_second_is(":").unwrap());
+            assert_eq!(reader.next_string("token").unwrap(), "c");
+        }
+    }
+}
@@ -0,0 +1,429 @@
+//! Opening bookshelf files that may be compressed or packed in a tarball.
+//!
+//! A path can go *through* an archive, e.g.
+//! `ispd2011.tar.gz/superblue1/superblue1.nodes`: the first ancestor that is
+//! an archive on disk is opened and the rest of the path names the member.
+//! A single open streams the member out of the archive, which rescans it from
+//! its start; [`Unpacked`] copies many members out in one pass instead, for
+//! readers that open them all at once.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsStr,
+    fs::File,
+    io::{self, BufReader, Read},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+pub type Source = Box<dyn Read + Send>;
+
+const COMPRESSION_EXTENSIONS: [&str; 3] = ["gz", "xz", "bz2"];
+const ARCHIVE_SUFFIXES: [&str; 7] = [
+    ".tar", ".tar.gz", ".tgz", ".tar.xz", ".txz", ".tar.bz2", ".tbz2",
+];
+const TAR_BLOCK: u64 = 512;
+
+/// Open `path` for reading, decompressing and looking into archives as needed.
+/// A missing plain file falls back to its `.gz`/`.xz`/`.bz2` sibling.
+pub fn open(path: &Path) -> anyhow::Result<Source> {
+    if let Some((archive, member)) = split_archive_path(path) {
+        return open_member(&archive, &member);
+    }
+    if !path.exists() {
+        for ext in COMPRESSION_EXTENSIONS {
+            let sibling = with_appended_extension(path, ext);
+            if sibling.is_file() {
+                return open_file(&sibling);
+            }
+        }
+    }
+    open_file(path)
+}
+
+/// The extension describing the bookshelf format, ignoring any compression
+/// suffix: `a.nodes.gz` gives `nodes`.
+pub fn format_extension(path: &Path) -> Option<&OsStr> {
+    match path.extension() {
+        Some(ext) if is_compression_extension(ext) => Path::new(path.file_stem()?).extension(),
+        ext => ext,
+    }
+}
+
+/// Split a path going through an archive into `(archive, member)`.
+pub fn split_archive_path(path: &Path) -> Option<(PathBuf, PathBuf)> {
+    for archive in path.ancestors().skip(1) {
+        if is_archive(archive) && archive.is_file() {
+            let member = path.strip_prefix(archive).ok()?.to_path_buf();
+            return Some((archive.to_path_buf(), member));
+        }
+    }
+    None
+}
+
+fn is_archive(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    ARCHIVE_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+}
+
+fn is_compression_extension(ext: &OsStr) -> bool {
+    COMPRESSION_EXTENSIONS.iter().any(|x| ext == *x)
+}
+
+fn with_appended_extension(path: &Path, ext: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(ext);
+    PathBuf::from(name)
+}
+
+fn open_file(path: &Path) -> anyhow::Result<Source> {
+    let file = File::open(path)
+        .map_err(|e| anyhow::anyhow!("failed to open {}: {}", path.display(), e))?;
+    Ok(decompress(Box::new(BufReader::new(file)), path))
+}
+
+/// Wrap `reader` in the decoder matching the (last) extension of `name`.
+fn decompress(reader: Source, name: &Path) -> Source {
+    let name = name.to_string_lossy();
+    if name.ends_with(".gz") || name.ends_with(".tgz") {
+        Box::new(flate2::read::MultiGzDecoder::new(reader))
+    } else if name.ends_with(".xz") || name.ends_with(".txz") {
+        Box::new(xz2::read::XzDecoder::new_multi_decoder(reader))
+    } else if name.ends_with(".bz2") || name.ends_with(".tbz2") {
+        Box::new(bzip2::read::MultiBzDecoder::new(reader))
+    } else {
+        reader
+    }
+}
+
+/// Does the archive entry `entry` name `member`? Entries may carry a leading
+/// `./` or extra parent directories, and may be compressed themselves.
+fn member_matches(entry: &str, member: &Path) -> Option<PathBuf> {
+    let entry = Path::new(entry.trim_start_matches("./"));
+    let mut candidates = vec![member.to_path_buf()];
+    candidates.extend(
+        COMPRESSION_EXTENSIONS
+            .iter()
+            .map(|ext| with_appended_extension(member, ext)),
+    );
+    candidates
+        .into_iter()
+        .find(|candidate| entry.ends_with(candidate))
+}
+
+/// A regular file or other entry of a tar archive, named by its header or
+/// the GNU long name or PAX header before it.
+struct Entry {
+    name: String,
+    size: u64,
+    is_file: bool,
+}
+
+impl Entry {
+    /// Size of the data in the archive, padded to whole blocks.
+    fn padded(&self) -> u64 {
+        self.size.div_ceil(TAR_BLOCK) * TAR_BLOCK
+    }
+}
+
+/// Read the header of the next entry of a tar stream, which is left at the
+/// start of the entry's data. `None` at the end of the archive.
+fn next_entry(reader: &mut Source, archive: &Path) -> anyhow::Result<Option<Entry>> {
+    let mut long_name: Option<String> = None;
+    let mut header = [0u8; TAR_BLOCK as usize];
+    loop {
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        if header.iter().all(|x| *x == 0) {
+            return Ok(None);
+        }
+        let size = parse_octal(&header[124..136])
+            .ok_or_else(|| anyhow::anyhow!("{}: corrupt tar header", archive.display()))?;
+        let padded = size.div_ceil(TAR_BLOCK) * TAR_BLOCK;
+        match header[156] {
+            // GNU long name and PAX extended header: the name of the next entry
+            b'L' | b'x' => {
+                let mut data = vec![0u8; padded as usize];
+                reader.read_exact(&mut data)?;
+                data.truncate(size as usize);
+                long_name = if header[156] == b'L' {
+                    Some(c_string(&data))
+                } else {
+                    pax_path(&data)
+                };
+            }
+            _ => {
+                return Ok(Some(Entry {
+                    name: long_name.unwrap_or_else(|| ustar_name(&header)),
+                    size,
+                    is_file: matches!(header[156], b'0' | 0),
+                }));
+            }
+        }
+    }
+}
+
+fn skip(reader: &mut Source, len: u64) -> anyhow::Result<()> {
+    io::copy(&mut reader.take(len), &mut io::sink())?;
+    Ok(())
+}
+
+/// Stream `member` out of a (possibly compressed) tar archive.
+fn open_member(archive: &Path, member: &Path) -> anyhow::Result<Source> {
+    let mut reader = open_file(archive)?;
+    while let Some(entry) = next_entry(&mut reader, archive)? {
+        if let Some(found) = member_matches(&entry.name, member).filter(|_| entry.is_file) {
+            return Ok(decompress(Box::new(reader.take(entry.size)), &found));
+        }
+        skip(&mut reader, entry.padded())?;
+    }
+    Err(anyhow::anyhow!(
+        "{} not found in archive {}",
+        member.display(),
+        archive.display()
+    ))
+}
+
+/// Copies of archive members, unpacked in one pass per archive into a
+/// directory of their own that is removed on drop. Without archives, this is
+/// free and every path reads in place.
+#[derive(Default)]
+pub struct Unpacked {
+    dir: Option<PathBuf>,
+    copies: HashMap<PathBuf, PathBuf>,
+}
+
+impl Unpacked {
+    /// Unpack the `paths` that go through an archive. A member missing from
+    /// its archive gets no copy, so opening it reports the error as usual.
+    pub fn new<'a>(paths: impl IntoIterator<Item = &'a Path>) -> anyhow::Result<Self> {
+        let mut by_archive: BTreeMap<PathBuf, Vec<(PathBuf, PathBuf)>> = BTreeMap::new();
+        for path in paths {
+            if let Some((archive, member)) = split_archive_path(path) {
+                by_archive
+                    .entry(archive)
+                    .or_default()
+                    .push((path.to_path_buf(), member));
+            }
+        }
+        let mut res = Self::default();
+        if by_archive.is_empty() {
+            return Ok(res);
+        }
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "bookshelf2lefdef-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        res.dir = Some(dir.clone());
+        for (id, (archive, members)) in by_archive.into_iter().enumerate() {
+            let name = archive.file_name().unwrap_or_default().to_string_lossy();
+            let archive_dir = dir.join(format!("{}-{}", id, name));
+            res.unpack(&archive, members, &archive_dir)?;
+        }
+        Ok(res)
+    }
+
+    /// Copy `members`, `(path, member)` pairs, out of `archive` into `dir`.
+    /// Members stay as stored, compressed or not, and keep the file name found
+    /// in the archive, so opening the copy decompresses it.
+    fn unpack(
+        &mut self,
+        archive: &Path,
+        mut members: Vec<(PathBuf, PathBuf)>,
+        dir: &Path,
+    ) -> anyhow::Result<()> {
+        let mut reader = open_file(archive)?;
+        while !members.is_empty() {
+            let Some(entry) = next_entry(&mut reader, archive)? else {
+                break;
+            };
+            let found = members.iter().enumerate().find_map(|(id, (_, member))| {
+                let found = member_matches(&entry.name, member).filter(|_| entry.is_file)?;
+                Some((id, found))
+            });
+            let Some((id, found)) = found else {
+                skip(&mut reader, entry.padded())?;
+                continue;
+            };
+            let (path, _) = members.swap_remove(id);
+            // one directory per member, as two members may share a file name
+            let member_dir = dir.join(self.copies.len().to_string());
+            std::fs::create_dir_all(&member_dir)?;
+            let copy = member_dir.join(found.file_name().unwrap_or_default());
+            let mut file = File::create(&copy)
+                .map_err(|e| anyhow::anyhow!("failed to create {}: {}", copy.display(), e))?;
+            io::copy(&mut (&mut reader).take(entry.size), &mut file)?;
+            skip(&mut reader, entry.padded() - entry.size)?;
+            self.copies.insert(path, copy);
+        }
+        Ok(())
+    }
+
+    /// Where to read `path`: its unpacked copy, or `path` itself.
+    pub fn get<'a>(&'a self, path: &'a Path) -> &'a Path {
+        self.copies.get(path).map_or(path, PathBuf::as_path)
+    }
+}
+
+impl Drop for Unpacked {
+    fn drop(&mut self) {
+        if let Some(dir) = self.dir.as_ref() {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+fn c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|x| *x == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+fn ustar_name(header: &[u8]) -> String {
+    let name = c_string(&header[0..100]);
+    let prefix = if &header[257..262] == b"ustar" {
+        c_string(&header[345..500])
+    } else {
+        String::new()
+    };
+    if prefix.is_empty() {
+        name
+    } else {
+        format!("{}/{}", prefix, name)
+    }
+}
+
+fn parse_octal(field: &[u8]) -> Option<u64> {
+    let text = c_string(field);
+    let text = text.trim();
+    if text.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(text, 8).ok()
+}
+
+/// Records look like `"<len> path=<value>\n"`.
+fn pax_path(data: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(data)
+        .lines()
+        .filter_map(|record| record.split_once(' ').map(|(_, kv)| kv))
+        .find_map(|kv| kv.strip_prefix("path=").map(str::to_string))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::io::test_util::temp_file;
+
+    /// A tar entry: a header block with `name` (and `prefix`, ustar only),
+    /// then `data` padded to whole blocks.
+    fn entry(kind: u8, name: &str, prefix: &str, data: &[u8]) -> Vec<u8> {
+        let mut header = [0u8; TAR_BLOCK as usize];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        let size = format!("{:011o}\0", data.len());
+        header[124..136].copy_from_slice(size.as_bytes());
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+        let mut res = header.to_vec();
+        res.extend_from_slice(data);
+        res.resize(
+            res.len().div_ceil(TAR_BLOCK as usize) * TAR_BLOCK as usize,
+            0,
+        );
+        res
+    }
+
+    fn archive() -> Vec<u8> {
+        let long = format!("bench/{}/c.pl", "d".repeat(120));
+        // the record length counts itself, three digits here
+        let record = format!(" path={}\n", long.replace("c.pl", "d.scl"));
+        let pax = format!("{}{}", record.len() + 3, record);
+        let mut res = vec![];
+        res.extend(entry(b'5', "bench/", "", b""));
+        res.extend(entry(b'0', "./bench/a.nodes", "", b"a nodes"));
+        res.extend(entry(b'0', "b.nets", "bench", b"b nets"));
+        res.extend(entry(
+            b'L',
+            "././@LongLink",
+            "",
+            format!("{}\0", long).as_bytes(),
+        ));
+        res.extend(entry(b'0', "truncated", "", b"c pl"));
+        res.extend(entry(b'x', "PaxHeader", "", pax.as_bytes()));
+        res.extend(entry(b'0', "truncated", "", b"d scl"));
+        res.extend([0u8; 2 * TAR_BLOCK as usize]);
+        res
+    }
+
+    fn read(path: &Path) -> String {
+        let mut res = String::new();
+        open(path).unwrap().read_to_string(&mut res).unwrap();
+        res
+    }
+
+    #[test]
+    fn reads_members_by_ustar_gnu_and_pax_names() {
+        let tar = temp_file("bench.tar", &archive());
+        assert_eq!(read(&tar.join("bench/a.nodes")), "a nodes");
+        assert_eq!(read(&tar.join("bench/b.nets")), "b nets");
+        let long = format!("{}/c.pl", "d".repeat(120));
+        assert_eq!(read(&tar.join(long)), "c pl");
+        let long = format!("{}/d.scl", "d".repeat(120));
+        assert_eq!(read(&tar.join(long)), "d scl");
+        assert!(open(&tar.join("bench")).is_err());
+        assert!(open(&tar.join("bench/missing.pl")).is_err());
+    }
+
+    #[test]
+    fn reads_members_of_compressed_archives() {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::fast());
+        encoder.write_all(&archive()).unwrap();
+        let tgz = temp_file("bench.tgz", &encoder.finish().unwrap());
+        assert_eq!(read(&tgz.join("a.nodes")), "a nodes");
+        assert_eq!(
+            split_archive_path(&tgz.join("x/y.pl")).unwrap().1,
+            Path::new("x/y.pl")
+        );
+    }
+
+    #[test]
+    fn unpacks_members_in_one_pass_until_dropped() {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::fast());
+        encoder.write_all(&archive()).unwrap();
+        let tgz = temp_file("unpack.tgz", &encoder.finish().unwrap());
+        let plain = temp_file("unpack.wts", b"weights");
+        let long = tgz.join(format!("{}/d.scl", "d".repeat(120)));
+        let missing = tgz.join("missing.pl");
+        let paths = [
+            tgz.join("a.nodes"),
+            tgz.join("b.nets"),
+            long,
+            missing,
+            plain,
+        ];
+        let unpacked = Unpacked::new(paths.iter().map(PathBuf::as_path)).unwrap();
+        let texts: Vec<_> = paths[..3].iter().map(|x| read(unpacked.get(x))).collect();
+        assert_eq!(texts, ["a nodes", "b nets", "d scl"]);
+        assert!(unpacked.get(&paths[0]).exists());
+        assert!(open(unpacked.get(&paths[3])).is_err());
+        assert_eq!(unpacked.get(&paths[4]), paths[4]);
+        let dir = unpacked.dir.clone().unwrap();
+        drop(unpacked);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn format_extension_skips_compression() {
+        assert_eq!(format_extension(Path::new("a.nodes.gz")).unwrap(), "nodes");
+        assert_eq!(format_extension(Path::new("a.pl")).unwrap(), "pl");
+        assert!(format_extension(Path::new("a.xz")).is_none());
+    }
+}
@@ -0,0 +1,86 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    diagnostics::{Diagnostics, ParseMode},
+    nets::Nets,
+    nodes::Nodes,
+    parser::Bookshelf,
+    pl::Pls,
+    route::Route,
+    scl::Scl,
+    shape::Shapes,
+};
+
+/// Write `content` to a file named `name` in a directory of its own under
+/// the system temp directory, for tests of readers that take a path.
+pub(crate) fn temp_file(name: &str, content: &[u8]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "bookshelf2lefdef-{}-{}",
+        std::process::id(),
+        name.replace('/', "_")
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, content).unwrap();
+    path
+}
+
+/// Read `content`, written to a file named `name`, with `read` in `mode`:
+/// what it returns, and the diagnostics it collected as messages.
+pub(crate) fn read_fixture<T>(
+    name: &str,
+    content: &[u8],
+    mode: ParseMode,
+    read: impl FnOnce(&Path, &mut Diagnostics) -> anyhow::Result<T>,
+) -> (anyhow::Result<T>, Vec<String>) {
+    let path = temp_file(name, content);
+    let mut diagnostics = Diagnostics::new(mode);
+    let res = read(&path, &mut diagnostics);
+    (res, diagnostics.iter().map(|x| x.to_string()).collect())
+}
+
+/// `messages` from the fixture `name` with everything up to its name cut
+/// off, so that they start at the `:line` after the path.
+pub(crate) fn after_name<'a>(messages: &'a [String], name: &str) -> Vec<&'a str> {
+    messages
+        .iter()
+        .map(|x| x.rsplit_once(name).map_or(x.as_str(), |(_, rest)| rest))
+        .collect()
+}
+
+/// A fixed 4 x 4 node `m` at (10, 10) whose `.shapes` leave out its upper
+/// right quarter.
+pub(crate) const RECTILINEAR: [(&str, &[u8]); 3] = [
+    ("nodes", b"UCLA nodes 1.0\nm 4 4 terminal\n"),
+    ("pl", b"UCLA pl 1.0\nm 10 10 : N /FIXED\n"),
+    (
+        "shapes",
+        b"shapes 1.0\nm : 2\nShape_0 10 10 4 2\nShape_1 10 12 2 2\n",
+    ),
+];
+
+/// A benchmark read in strict mode from `files`, pairs of an extension
+/// (`nodes`, `nets`, `pl`, `scl`, `shapes` or `route`) and its content,
+/// written as `name.<extension>`.
+pub(crate) fn read_bookshelf(name: &str, files: &[(&str, &[u8])]) -> Bookshelf {
+    let mut res = Bookshelf::default();
+    for (extension, content) in files {
+        let name = format!("{}.{}", name, extension);
+        let read = |path: &Path, diagnostics: &mut Diagnostics| -> anyhow::Result<()> {
+            match *extension {
+                "nodes" => res.nodes = Nodes::read(path, diagnostics)?,
+                "nets" => res.nets = Nets::read_from_file(path.to_path_buf(), diagnostics)?,
+                "pl" => res.pls = Pls::read_from_file(path, diagnostics)?,
+                "scl" => res.scl = Scl::read_from_file(path, diagnostics)?,
+                "shapes" => res.shape = Shapes::read_from_file(path, diagnostics)?,
+                "route" => res.route = Route::read(path, diagnostics)?,
+                _ => panic!("no reader for .{}", extension),
+            }
+            Ok(())
+        };
+        read_fixture(&name, content, ParseMode::Strict, read)
+            .0
+            .unwrap();
+    }
+    res
+}
@@ -0,0 +1,395 @@
+use log::{info, warn};
+
+use crate::{
+    geom::{Point, Rect},
+    lefdef::{
+        options::{Options, PorosityStrategy},
+        techlef::TechLef,
+        units::to_dbu,
+        writer::RoutingLayer,
+    },
+    parser::Bookshelf,
+};
+
+struct PlacementBlockage {
+    rect: Rect,
+    /// Max placement density in percent for `+ PARTIAL`, `None` for a hard
+    /// blockage.
+    partial: Option<f64>,
+}
+
+struct RoutingBlockage {
+    layer: String,
+    rect: Rect,
+}
+
+/// Tracks of a layer crossing a blocked rectangle: how many there are and
+/// how many the striped strategy leaves free.
+#[derive(Default)]
+struct TrackCount {
+    total: usize,
+    free: usize,
+}
+
+/// Blocked stripes of `rect` on `layer`, leaving about `porosity` of the
+/// tracks crossing it free and evenly spread, with the tracks counted in
+/// `count`. Each blocked track covers one pitch around it, clipped to `rect`.
+fn stripes(
+    rect: &Rect,
+    layer: &RoutingLayer,
+    origin: Point,
+    porosity: f64,
+    count: &mut TrackCount,
+) -> Vec<Rect> {
+    let (lo, hi, origin) = match layer.is_vertical() {
+        true => (rect.ll.x, rect.ur.x, origin.x + layer.offset()),
+        false => (rect.ll.y, rect.ur.y, origin.y + layer.offset()),
+    };
+    let pitch = layer.pitch();
+    let first = ((lo - origin) / pitch).ceil() as i64;
+    let last = ((hi - origin) / pitch).floor() as i64;
+    let mut spans: Vec<(f64, f64)> = vec![];
+    let mut run: Option<(f64, f64)> = None;
+    for (i, track_id) in (first..=last).enumerate() {
+        count.total += 1;
+        let is_free = ((i + 1) as f64 * porosity).floor() > (i as f64 * porosity).floor();
+        if is_free {
+            count.free += 1;
+            spans.extend(run.take());
+            continue;
+        }
+        let track = origin + track_id as f64 * pitch;
+        let (start, end) = ((track - pitch / 2.0).max(lo), (track + pitch / 2.0).min(hi));
+        run = Some(match run {
+            Some((run_start, _)) => (run_start, end),
+            None => (start, end),
+        });
+    }
+    spans.extend(run);
+    spans
+        .into_iter()
+        .map(|(start, end)| match layer.is_vertical() {
+            true => Rect {
+                ll: Point {
+                    x: start,
+                    y: rect.ll.y,
+                },
+                ur: Point {
+                    x: end,
+                    y: rect.ur.y,
+                },
+            },
+            false => Rect {
+                ll: Point {
+                    x: rect.ll.x,
+                    y: start,
+                },
+                ur: Point {
+                    x: rect.ur.x,
+                    y: end,
+                },
+            },
+        })
+        .collect()
+}
+
+/// DEF BLOCKAGES.
+#[derive(Default)]
+pub struct Blockages {
+    placement: Vec<PlacementBlockage>,
+    routing: Vec<RoutingBlockage>,
+}
+
+impl Blockages {
+    /// A rectilinear node blocks placement on its `.shapes` rectangles only,
+    /// not on its whole bounding box. Nodes `.route` blocks get the partial
+    /// placement or striped routing blockages `options.porosity` asks for.
+    pub fn build(bookshelf: &Bookshelf, techlef: &TechLef, options: &Options) -> Self {
+        let placement = bookshelf
+            .shape
+            .iter()
+            .flat_map(|node_shape| node_shape.rects().copied())
+            .map(|rect| PlacementBlockage {
+                rect,
+                partial: None,
+            })
+            .collect();
+        let mut res = Self {
+            placement,
+            routing: vec![],
+        };
+        res.build_porosity(bookshelf, techlef, options);
+        res
+    }
+
+    fn build_porosity(&mut self, bookshelf: &Bookshelf, techlef: &TechLef, options: &Options) {
+        let route = &bookshelf.route;
+        if route.blockage_info.is_empty() {
+            return;
+        }
+        let porosity = route.porosity();
+        let mut count = TrackCount::default();
+        for (name, layer_ids) in route.blockage_info.iter() {
+            let Some(rects) = bookshelf.footprint(name) else {
+                warn!(
+                    "{} is blocked in .route, but it is not placed; ignored",
+                    name
+                );
+                continue;
+            };
+            if options.porosity == PorosityStrategy::PartialPlacement {
+                self.placement
+                    .extend(rects.iter().map(|rect| PlacementBlockage {
+                        rect: *rect,
+                        partial: Some(porosity * 100.0),
+                    }));
+            }
+            let layers = layer_ids
+                .iter()
+                .filter_map(|id| usize::try_from(id - 1).ok())
+                .filter_map(|id| techlef.layer.get(id));
+            for layer in layers {
+                for rect in rects.iter() {
+                    let stripes = stripes(rect, layer, route.grid_origin, porosity, &mut count);
+                    if options.porosity == PorosityStrategy::Striped {
+                        self.routing
+                            .extend(stripes.into_iter().map(|rect| RoutingBlockage {
+                                layer: layer.name().to_string(),
+                                rect,
+                            }));
+                    }
+                }
+            }
+        }
+        info!("{}", Self::report(porosity, &count, options.porosity));
+    }
+
+    /// The routing capacity over blocked nodes each strategy keeps, next to
+    /// what the bookshelf porosity asks for.
+    fn report(porosity: f64, count: &TrackCount, selected: PorosityStrategy) -> String {
+        let striped = match count.total {
+            0 => porosity,
+            total => count.free as f64 / total as f64,
+        };
+        let mut table = format!(
+            "Routing capacity kept over blocked nodes ({} tracks):\
+            \n    {:<20} {:>6.1}%",
+            count.total,
+            "bookshelf",
+            porosity * 100.0
+        );
+        for (strategy, kept) in [
+            (PorosityStrategy::Obs, 0.0),
+            (PorosityStrategy::PartialPlacement, 1.0),
+            (PorosityStrategy::Striped, striped),
+        ] {
+            let mark = if strategy == selected { " <-" } else { "" };
+            table += &format!(
+                "\n    {:<20} {:>6.1}%{}",
+                strategy.to_string(),
+                kept * 100.0,
+                mark
+            );
+        }
+        table
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.placement.is_empty() && self.routing.is_empty()
+    }
+
+    pub fn write(&self) -> String {
+        if self.is_empty() {
+            return String::new();
+        }
+        let write_rect = |rect: &Rect| {
+            format!(
+                "RECT ( {} {} ) ( {} {} ) ;",
+                to_dbu(rect.ll.x),
+                to_dbu(rect.ll.y),
+                to_dbu(rect.ur.x),
+                to_dbu(rect.ur.y)
+            )
+        };
+        let mut res = format!(
+            "\nBLOCKAGES {} ;",
+            self.placement.len() + self.routing.len()
+        );
+        for blockage in self.routing.iter() {
+            res += &format!(
+                "\n- LAYER {} {}",
+                blockage.layer,
+                write_rect(&blockage.rect)
+            );
+        }
+        for blockage in self.placement.iter() {
+            // DEF takes a percentage
+            let partial = match blockage.partial {
+                Some(density) => format!("+ PARTIAL {:.1} ", density.clamp(0.0, 100.0)),
+                None => String::new(),
+            };
+            res += &format!("\n- PLACEMENT {}{}", partial, write_rect(&blockage.rect));
+        }
+        res += "\nEND BLOCKAGES";
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::test_util::{RECTILINEAR, read_bookshelf};
+
+    /// metal1 horizontal and metal2 vertical, both of pitch 1 with the first
+    /// track at 0.5.
+    const ROUTE: &[u8] = b"route 1.0\nGrid : 4 4 2\n\
+        VerticalCapacity : 0 10\nHorizontalCapacity : 10 0\n\
+        MinWireWidth : 0.5 0.5\nMinWireSpacing : 0.5 0.5\nViaSpacing : 0 0\n\
+        GridOrigin : 0 0\nTileSize : 10 10\nBlockagePorosity : 0.5\n\
+        NumBlockageNodes : 1\nm 2 1 2\n";
+
+    async fn layers() -> Vec<RoutingLayer> {
+        let bookshelf = read_bookshelf("layers", &[("route", ROUTE)]);
+        RoutingLayer::build_routing_layers(&bookshelf, &Options::default())
+            .await
+            .unwrap()
+    }
+
+    fn corners(rects: Vec<Rect>) -> Vec<(f64, f64, f64, f64)> {
+        rects
+            .iter()
+            .map(|x| (x.ll.x, x.ll.y, x.ur.x, x.ur.y))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn stripes_leave_the_porosity_of_the_tracks_free() {
+        let layers = layers().await;
+        let rect = Rect {
+            ll: Point { x: 10.0, y: 10.0 },
+            ur: Point { x: 14.0, y: 12.0 },
+        };
+        let stripes = |layer: &RoutingLayer, porosity| {
+            let mut count = TrackCount::default();
+            let res = stripes(&rect, layer, Point::default(), porosity, &mut count);
+            (corners(res), count.free, count.total)
+        };
+        // blocked neighbours make one stripe
+        assert_eq!(
+            stripes(&layers[1], 0.0),
+            (vec![(10.0, 10.0, 14.0, 12.0)], 0, 4)
+        );
+        assert_eq!(
+            stripes(&layers[1], 0.5),
+            (
+                vec![(10.0, 10.0, 11.0, 12.0), (12.0, 10.0, 13.0, 12.0)],
+                2,
+                4
+            )
+        );
+        assert_eq!(
+            stripes(&layers[1], 0.25),
+            (vec![(10.0, 10.0, 13.0, 12.0)], 1, 4)
+        );
+        assert_eq!(stripes(&layers[1], 1.0), (vec![], 4, 4));
+        // horizontal tracks cut the rect across
+        assert_eq!(
+            stripes(&layers[0], 0.5),
+            (vec![(10.0, 10.0, 14.0, 11.0)], 1, 2)
+        );
+    }
+
+    #[tokio::test]
+    async fn striped_blockages_cover_each_blocked_layer() {
+        let files = [RECTILINEAR.as_slice(), &[("route", ROUTE)]].concat();
+        let bookshelf = read_bookshelf("striped", &files);
+        let options = Options {
+            porosity: PorosityStrategy::Striped,
+            ..Default::default()
+        };
+        let blockages = Blockages::build(
+            &bookshelf,
+            &TechLef {
+                layer: layers().await,
+            },
+            &options,
+        );
+        let routing: Vec<_> = blockages
+            .routing
+            .iter()
+            .map(|x| (x.layer.as_str(), corners(vec![x.rect])[0]))
+            .collect();
+        assert_eq!(
+            routing,
+            [
+                ("metal1", (10.0, 10.0, 14.0, 11.0)),
+                ("metal1", (10.0, 12.0, 12.0, 13.0)),
+                ("metal2", (10.0, 10.0, 11.0, 12.0)),
+                ("metal2", (12.0, 10.0, 13.0, 12.0)),
+                ("metal2", (10.0, 12.0, 11.0, 14.0)),
+            ]
+        );
+        assert_eq!(blockages.placement.len(), 2);
+    }
+
+    #[test]
+    fn report_compares_the_strategies() {
+        let count = TrackCount { total: 8, free: 2 };
+        let report = Blockages::report(0.5, &count, PorosityStrategy::Striped);
+        let rows: Vec<Vec<&str>> = report
+            .lines()
+            .skip(1)
+            .map(|x| x.split_whitespace().collect())
+            .collect();
+        assert!(report.starts_with("Routing capacity kept over blocked nodes (8 tracks):"));
+        assert_eq!(
+            rows,
+            [
+                vec!["bookshelf", "50.0%"],
+                vec!["obs", "0.0%"],
+                vec!["partial-placement", "100.0%"],
+                vec!["striped", "25.0%", "<-"],
+            ]
+        );
+        // without tracks, striping is taken to keep what was asked
+        let report = Blockages::report(0.5, &TrackCount::default(), PorosityStrategy::Obs);
+        let last = report.lines().last().unwrap();
+        assert_eq!(
+            last.split_whitespace().collect::<Vec<_>>(),
+            ["striped", "50.0%"]
+        );
+        assert!(report.contains("obs                     0.0% <-\n"));
+    }
+
+    #[test]
+    fn partial_density_is_a_percentage() {
+        let blockage = |partial| PlacementBlockage {
+            rect: Rect::default(),
+            partial: Some(partial),
+        };
+        let blockages = Blockages {
+            placement: vec![blockage(150.0), blockage(-5.0), blockage(25.0)],
+            routing: vec![],
+        };
+        let text = blockages.write();
+        let densities: Vec<&str> = text
+            .lines()
+            .filter_map(|x| x.split_whitespace().nth(4))
+            .collect();
+        assert_eq!(densities, ["100.0", "0.0", "25.0"]);
+    }
+
+    #[test]
+    fn placement_blockages_cover_the_shapes_only() {
+        let bookshelf = read_bookshelf("placement_blockages", &RECTILINEAR);
+        let techlef = TechLef { layer: vec![] };
+        let blockages = Blockages::build(&bookshelf, &techlef, &Options::default());
+        assert_eq!(
+            blockages.write(),
+            "\nBLOCKAGES 2 ;\
+            \n- PLACEMENT RECT ( 10000 10000 ) ( 14000 12000 ) ;\
+            \n- PLACEMENT RECT ( 10000 12000 ) ( 12000 14000 ) ;\
+            \nEND BLOCKAGES"
+        );
+    }
+}
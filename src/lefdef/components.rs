@@ -1,4 +1,9 @@
-use crate::parser::Bookshelf;
+use log::warn;
+
+use crate::{
+    lefdef::{options::Options, units::to_dbu, writer::{MacroClass, Macros}},
+    parser::Bookshelf,
+};
 
 pub struct Components {
     num: i64,
@@ -6,23 +11,47 @@ pub struct Components {
 }
 
 impl Components {
-    pub fn build(bookshelf: &Bookshelf) -> Self {
+    pub fn build(bookshelf: &Bookshelf, macros: &Macros, options: &Options) -> Self {
         let mut res = String::new();
         let mut num_comp = 0;
+        let mut not_allowed = vec![];
         for node in bookshelf.nodes.iter() {
             let moveable = match node.moveable {
                 crate::nodes::Movable::Movable => "PLACED",
                 crate::nodes::Movable::Fixed => "FIXED",
                 crate::nodes::Movable::FixedButOverlapAllowed => continue,
             };
-            let place = &bookshelf.pls.get(&node.name).unwrap().place.clone();
+            let pl = bookshelf.pls.get(&node.name).unwrap();
+            let master = macros.master_of(&node.name).unwrap();
+            let moveable = match master.class {
+                MacroClass::Cover => "COVER",
+                _ => moveable,
+            };
+            let symmetry = master.symmetry;
+            if !symmetry.allows(pl.orientation) {
+                not_allowed.push(format!("{} ({} vs SYMMETRY {})", node.name, pl.orientation, symmetry));
+            }
             num_comp += 1;
-            res += &format!("\n- {} {} + {} ( {} {} ) N ;",
-                node.name,
+            res += &format!("\n- {} {} + {} ( {} {} ) {}",
                 node.name,
+                master.name,
                 moveable,
-                (place.x as i64) * 1000,
-                (place.y as i64) * 1000,
+                to_dbu(pl.place.x),
+                to_dbu(pl.place.y),
+                pl.orientation,
+            );
+            if options.component_weights
+                && let Some(weight) = bookshelf.wts.get_for_def(&node.name)
+            {
+                res += &format!(" + WEIGHT {}", weight);
+            }
+            res += " ;";
+        }
+        if !not_allowed.is_empty() {
+            warn!(
+                "{} component(s) are placed in an orientation their macro's SYMMETRY does not allow, e.g. {}",
+                not_allowed.len(),
+                not_allowed[..not_allowed.len().min(5)].join(", ")
             );
         }
         Self{to_print:res, num: num_comp}
@@ -31,7 +60,7 @@ impl Components {
     pub fn write(&self) -> String {
         let mut res = format!("\nCOMPONENTS {} ;", self.num);
         res += &self.to_print;
-        res += &format!("\nEND COMPONENTS");
+        res += "\nEND COMPONENTS";
         res        
     }
 }
\ No newline at end of file
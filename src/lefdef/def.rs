@@ -1,7 +1,7 @@
 use std::{io::Write, path::PathBuf};
 
 use crate::{
-    lefdef::{components::Components, die_area::DieArea, lef::Lef, net::Nets, pin::DefPins, row::Rows, tracks::Tracks},
+    lefdef::{blockages::Blockages, components::Components, die_area::DieArea, gcell_grid::GCellGrid, lef::Lef, net::Nets, options::Options, pin::DefPins, row::Rows, techlef::TechLef, tracks::Tracks, units::DBU_PER_MICRON},
     parser::Bookshelf,
 };
 
@@ -11,18 +11,22 @@ pub struct Def {
     nets: Nets,
     components: Components,
     tracks: Tracks,
+    gcell_grid: GCellGrid,
     die_area: DieArea,
+    blockages: Blockages,
 }
 
 impl Def {
-    pub fn build(bookshelf: &Bookshelf, lef: &Lef) -> Self {
+    pub fn build(bookshelf: &Bookshelf, techlef: &TechLef, lef: &Lef, options: &Options) -> anyhow::Result<Self> {
         let die_area = DieArea::build(bookshelf);
-        let rows = Rows::build(bookshelf, format!("CoreSite"));
-        let tracks = Tracks::build(bookshelf);
-        let pins = DefPins::build(bookshelf);
-        let nets = Nets::build_net(&lef.macros);
-        let components = Components::build(bookshelf);
-        Self { pins, nets, rows, components, tracks, die_area }
+        let rows = Rows::build(bookshelf, "CoreSite".to_string());
+        let tracks = Tracks::build(bookshelf, techlef)?;
+        let gcell_grid = GCellGrid::build(bookshelf);
+        let pins = DefPins::build(bookshelf)?;
+        let nets = Nets::build_net(&lef.macros, &bookshelf.wts);
+        let components = Components::build(bookshelf, &lef.macros, options);
+        let blockages = Blockages::build(bookshelf, techlef, options);
+        Ok(Self { pins, nets, rows, components, tracks, gcell_grid, die_area, blockages })
     }
     pub fn write_to_file(&self, file_path: &PathBuf) -> anyhow::Result<()> {
         let mut file = std::fs::File::create(file_path)?;
@@ -31,10 +35,10 @@ impl Def {
             \nDIVIDERCHAR \"/\" ;\
             \nBUSBITCHARS \"[]\" ;\
             \nDESIGN auto_generated ;\
-            \nUNITS DISTANCE MICRONS 1000 ;\
-            {}{}{}{}{}{}
+            \nUNITS DISTANCE MICRONS {} ;\
+            {}{}{}{}{}{}{}{}
             \nEND DESIGN
-            ",self.die_area.write(), self.rows.write(), self.tracks.write(), self.components.write(), self.pins.write(), self.nets.write());
+            ",DBU_PER_MICRON, self.die_area.write(), self.rows.write(), self.tracks.write(), self.gcell_grid.write(), self.components.write(), self.pins.write(), self.blockages.write(), self.nets.write());
         file.write_all(to_write.as_bytes())?;
         Ok(())
     }
@@ -1,4 +1,4 @@
-use crate::{geom::{Point, Rect}, parser::Bookshelf};
+use crate::{geom::{Point, Rect}, lefdef::units::to_dbu, parser::Bookshelf};
 
 pub struct DieArea {
     die_area: Rect
@@ -15,10 +15,10 @@ impl DieArea {
     }
     pub fn write(&self) -> String {
         format!("\nDIEAREA ( {} {} ) ( {} {} ) ;",
-            (self.die_area.ll.x * 1000.0) as i64,
-            (self.die_area.ll.y * 1000.0) as i64,
-            (self.die_area.ur.x * 1000.0) as i64,
-            (self.die_area.ur.y * 1000.0) as i64,
+            to_dbu(self.die_area.ll.x),
+            to_dbu(self.die_area.ll.y),
+            to_dbu(self.die_area.ur.x),
+            to_dbu(self.die_area.ur.y),
         )
     }
 }
\ No newline at end of file
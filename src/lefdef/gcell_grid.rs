@@ -0,0 +1,106 @@
+use crate::{geom::Point, lefdef::units::to_dbu, parser::Bookshelf};
+
+/// DEF GCELLGRID: the global-routing tiles of `.route`, `Grid` tiles of
+/// `TileSize` from `GridOrigin`.
+pub struct GCellGrid {
+    origin: Point,
+    tile_size: Point,
+    num_x: i64,
+    num_y: i64,
+}
+
+/// The GCELLGRID lines of one axis, `num` tiles of `tile` from `origin`.
+/// `DO` counts grid lines, one more than tiles. A tile that is no whole
+/// number of database units, as when a made-up grid splits the die evenly,
+/// would leave the last line off the die edge, so that line gets a statement
+/// of its own.
+fn axis(name: &str, origin: f64, tile: f64, num: i64) -> String {
+    let start = to_dbu(origin);
+    let step = to_dbu(tile);
+    let edge = to_dbu(origin + tile * num as f64);
+    if start + step * num == edge {
+        return format!(
+            "\nGCELLGRID {} {} DO {} STEP {} ;",
+            name,
+            start,
+            num + 1,
+            step
+        );
+    }
+    format!(
+        "\nGCELLGRID {} {} DO {} STEP {} ;\
+        \nGCELLGRID {} {} DO 1 STEP {} ;",
+        name, start, num, step, name, edge, step
+    )
+}
+
+impl GCellGrid {
+    pub fn build(bookshelf: &Bookshelf) -> Self {
+        let route = &bookshelf.route;
+        Self {
+            origin: route.grid_origin,
+            tile_size: route.tile_size,
+            num_x: route.grid.num_x,
+            num_y: route.grid.num_y,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.num_x <= 0 || self.num_y <= 0
+    }
+
+    pub fn write(&self) -> String {
+        if self.is_empty() {
+            return String::new();
+        }
+        axis("X", self.origin.x, self.tile_size.x, self.num_x)
+            + &axis("Y", self.origin.y, self.tile_size.y, self.num_y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{geom::Rect, lefdef::die_area::DieArea, route::Route};
+
+    fn write(die: Rect, tile_len: f64) -> (String, String) {
+        let bookshelf = Bookshelf {
+            route: Route::synthesize(die, tile_len, 2, 1.0),
+            ..Default::default()
+        };
+        let die_area = DieArea::build(&bookshelf).write();
+        (GCellGrid::build(&bookshelf).write(), die_area)
+    }
+
+    #[test]
+    fn grid_lines_end_on_the_die_edge() {
+        let die = Rect {
+            ll: Point { x: 1.0, y: 2.0 },
+            ur: Point { x: 41.0, y: 22.0 },
+        };
+        let (grid, die_area) = write(die, 10.0);
+        assert_eq!(
+            grid,
+            "\nGCELLGRID X 1000 DO 5 STEP 10000 ;\
+            \nGCELLGRID Y 2000 DO 3 STEP 10000 ;"
+        );
+        assert_eq!(die_area, "\nDIEAREA ( 1000 2000 ) ( 41000 22000 ) ;");
+    }
+
+    #[test]
+    fn uneven_tiles_close_the_grid_on_the_die_edge() {
+        // 25 by 18 in tiles of about 10: 3 of 8.333 by 2 of 9
+        let die = Rect {
+            ll: Point::default(),
+            ur: Point { x: 25.0, y: 18.0 },
+        };
+        let (grid, die_area) = write(die, 10.0);
+        assert_eq!(
+            grid,
+            "\nGCELLGRID X 0 DO 3 STEP 8333 ;\
+            \nGCELLGRID X 25000 DO 1 STEP 8333 ;\
+            \nGCELLGRID Y 0 DO 3 STEP 9000 ;"
+        );
+        assert_eq!(die_area, "\nDIEAREA ( 0 0 ) ( 25000 18000 ) ;");
+    }
+}
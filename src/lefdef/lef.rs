@@ -1,17 +1,24 @@
-use std::{io::Write, os::unix::ffi::OsStringExt, path::PathBuf};
+use std::{io::Write, path::PathBuf};
 
-use crate::{lefdef::writer::Macros, parser::Bookshelf};
+use crate::{geom::Symmetry, lefdef::{options::Options, techlef::TechLef, writer::Macros}, parser::Bookshelf};
 
 pub struct Lef {
     pub macros: Macros,
+    pub site_width: f64,
     pub site_height: f64,
+    pub site_symmetry: Symmetry,
 }
 
 impl Lef {
-    pub async fn build(bookshelf: &Bookshelf) -> anyhow::Result<Self> {
+    pub async fn build(bookshelf: &Bookshelf, techlef: &TechLef, options: &Options) -> anyhow::Result<Self> {
+        let Some(row) = bookshelf.scl.iter().next() else {
+            anyhow::bail!("cannot make the LEF site without rows in .scl");
+        };
         Ok(Self {
-            site_height: bookshelf.scl.iter().next().unwrap().height as f64,
-            macros: Macros::build_macro(bookshelf).await?,
+            site_width: row.site_width,
+            site_height: row.height,
+            site_symmetry: row.site_symmetry,
+            macros: Macros::build_macro(bookshelf, techlef, options).await?,
         })
     }
 
@@ -23,10 +30,10 @@ DIVIDERCHAR "/" ;
 
 SITE CoreSite 
     CLASS CORE ;
-    SYMMETRY Y ;
-    SIZE 1.000 BY {:.3} ;
+    SYMMETRY {} ;
+    SIZE {:.3} BY {:.3} ;
 END CoreSite
-"#, self.site_height);
+"#, self.site_symmetry, self.site_width, self.site_height);
         to_write += &self.macros.write_all();
         let mut f = std::fs::File::create(file_path)?;
         f.write_all(to_write.as_bytes())?;
@@ -1,10 +1,14 @@
+pub mod blockages;
 pub mod def;
 pub mod lef;
 pub mod net;
+pub mod options;
 pub mod pin;
 pub mod techlef;
 pub mod writer;
 pub mod row;
 pub mod components;
 pub mod tracks;
-pub mod die_area;
\ No newline at end of file
+pub mod units;
+pub mod die_area;
+pub mod gcell_grid;
\ No newline at end of file
@@ -1,6 +1,6 @@
 use std::collections::BTreeMap;
 
-use crate::lefdef::writer::Macros;
+use crate::{lefdef::writer::Macros, wts::Wts};
 
 #[derive(Debug, Clone)]
 pub enum Node {
@@ -10,12 +10,14 @@ pub enum Node {
 pub struct Net {
     pub name: String,
     pub nodes: Vec<Node>,
+    /// From `.wts`, `None` keeps the DEF default of 1.
+    pub weight: Option<i64>,
 }
 
 pub struct Nets(BTreeMap<String, Net>);
 
 impl Nets {
-    pub fn build_net(macros: &Macros) -> Self {
+    pub fn build_net(macros: &Macros, wts: &Wts) -> Self {
         Self(
             macros
                 .net_to_nodes
@@ -26,6 +28,7 @@ impl Nets {
                         Net {
                             name: net_name.clone(),
                             nodes: nodes.clone(),
+                            weight: wts.get_for_def(net_name),
                         },
                     )
                 })
@@ -49,9 +52,13 @@ impl Nets {
                     }
                 }
             }
-            res += " + USE SIGNAL ;";
+            res += " + USE SIGNAL";
+            if let Some(weight) = net.weight {
+                res += &format!(" + WEIGHT {}", weight);
+            }
+            res += " ;";
         }
-        res += &format!("\nEND NETS");
+        res += "\nEND NETS";
         res
     }
 }
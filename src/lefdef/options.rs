@@ -0,0 +1,104 @@
+use std::{fmt, str::FromStr};
+
+/// How `.route` blockages are written when `BlockagePorosity` leaves some
+/// capacity over blocked macros, which LEF OBS cannot express.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PorosityStrategy {
+    /// LEF OBS on each blocked layer: nothing is left.
+    #[default]
+    Obs,
+    /// No routing obstruction; a DEF partial placement blockage
+    /// (`+ PARTIAL <porosity>`) over each blocked macro instead.
+    PartialPlacement,
+    /// DEF routing blockages in stripes along each blocked layer, leaving
+    /// free the share of its tracks the porosity asks for.
+    Striped,
+}
+
+impl fmt::Display for PorosityStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PorosityStrategy::Obs => "obs",
+            PorosityStrategy::PartialPlacement => "partial-placement",
+            PorosityStrategy::Striped => "striped",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for PorosityStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "obs" => Ok(PorosityStrategy::Obs),
+            "partial-placement" => Ok(PorosityStrategy::PartialPlacement),
+            "striped" => Ok(PorosityStrategy::Striped),
+            _ => Err(format!(
+                "unknown porosity strategy `{}`, expected obs, partial-placement or striped",
+                s
+            )),
+        }
+    }
+}
+
+/// What to make of a `.route` layer with capacity in both directions, or in
+/// neither, which a LEF routing layer cannot express.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LayerPolicy {
+    /// The direction with more capacity; ties and layers without capacity
+    /// alternate with the layer below.
+    #[default]
+    Dominant,
+    /// The direction opposite to the layer below.
+    Alternate,
+    /// Alternate, but write no tracks, leaving the layer to pins and
+    /// obstructions.
+    PinOnly,
+}
+
+impl fmt::Display for LayerPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LayerPolicy::Dominant => "dominant",
+            LayerPolicy::Alternate => "alternate",
+            LayerPolicy::PinOnly => "pin-only",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for LayerPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "dominant" => Ok(LayerPolicy::Dominant),
+            "alternate" => Ok(LayerPolicy::Alternate),
+            "pin-only" => Ok(LayerPolicy::PinOnly),
+            _ => Err(format!(
+                "unknown layer policy `{}`, expected dominant, alternate or pin-only",
+                s
+            )),
+        }
+    }
+}
+
+/// Choices about the LEF/DEF output that the bookshelf input does not make.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Also write `.wts` weights as `+ WEIGHT` on DEF COMPONENTS. Net weights
+    /// are always written.
+    pub component_weights: bool,
+    pub porosity: PorosityStrategy,
+    /// Widen each layer's track pitch until a tile holds as many tracks as
+    /// its `.route` capacity, instead of packing them at width plus spacing.
+    pub capacity_pitch: bool,
+    pub layer_policy: LayerPolicy,
+    /// Edge of the square pin shapes of generated masters; the min wire
+    /// width of the pin layer when `None`.
+    pub pin_size: Option<f64>,
+    /// Move pin shapes onto track crossings of their layer, as seen from a
+    /// node on the site grid of the first row, so that masters stay shared.
+    pub snap_pins: bool,
+}
@@ -1,24 +1,54 @@
 use std::collections::{BTreeMap, BTreeSet};
 
-use log::info;
+use log::{info, warn};
 
-use crate::{geom::Point, parser::Bookshelf};
+use crate::{
+    geom::{Orient, Point, Rect},
+    lefdef::units::to_dbu,
+    nets::PinDirection,
+    parser::Bookshelf,
+};
 
 #[derive(Default)]
 pub struct DefPin {
-    pub name: String,              // aux.route
-    pub layer: String,             // aux.route
-    pub orientation: String,       // aux.pl
-    pub place: Point,              // aux.pl
-    pub shape: Point,              // aux.node
-    pub net: Option<String>,       // aux.net
-    pub direction: Option<String>, // aux.net
+    pub name: String,                    // aux.route
+    pub layer: String,                   // aux.route
+    pub orientation: Orient,             // aux.pl
+    pub place: Point,                    // aux.pl
+    pub shape: Point,                    // aux.node
+    pub net: Option<String>,             // aux.net
+    pub direction: Option<PinDirection>, // aux.net
 }
 
 impl DefPin {
+    /// The port around the pin's origin that, once DEF turns it by the
+    /// orientation, covers the terminal as placed: the `.nodes` size with
+    /// its sides swapped when rotated.
+    fn port(&self) -> Rect {
+        let placed = match self.orientation.is_rotated() {
+            true => Point {
+                x: self.shape.y,
+                y: self.shape.x,
+            },
+            false => self.shape,
+        };
+        let corner = self.orientation.inverse().apply(placed);
+        Rect {
+            ll: Point {
+                x: corner.x.min(0.0),
+                y: corner.y.min(0.0),
+            },
+            ur: Point {
+                x: corner.x.max(0.0),
+                y: corner.y.max(0.0),
+            },
+        }
+    }
+
     fn write_to_string(&self) -> String {
         match (&self.net, &self.direction) {
             (Some(net), Some(direction)) => {
+                let port = self.port();
                 format!(
                     "\
                 \n- {}\
@@ -26,20 +56,23 @@ impl DefPin {
                 \n    + DIRECTION {}\
                 \n    + USE SIGNAL\
                 \n    + PORT\
-                \n        + LAYER {} ( 0 0 ) ( {} {} )\
-                \n        + FIXED ( {} {} ) N ;",
+                \n        + LAYER {} ( {} {} ) ( {} {} )\
+                \n        + FIXED ( {} {} ) {} ;",
                     self.name,
                     net,
-                    direction,
+                    direction.lefdef_name(),
                     self.layer,
-                    (self.shape.x * 1000.0) as i64,
-                    (self.shape.y * 1000.0) as i64,
-                    (self.place.x * 1000.0) as i64,
-                    (self.place.y * 1000.0) as i64
+                    to_dbu(port.ll.x),
+                    to_dbu(port.ll.y),
+                    to_dbu(port.ur.x),
+                    to_dbu(port.ur.y),
+                    to_dbu(self.place.x),
+                    to_dbu(self.place.y),
+                    self.orientation,
                 )
             }
             _ => {
-                format!("")
+                String::new()
                 // TODO!
                 // format!(
                 //     "\
@@ -64,7 +97,8 @@ impl DefPin {
 pub struct PinValidator {
     pub pin_in_route: BTreeSet<String>,
     pub pin_in_pl: BTreeSet<String>,
-    pub pin_in_net: BTreeMap<String, i64>,
+    pub pin_in_node: BTreeSet<String>,
+    pub pin_in_net: BTreeMap<String, Vec<String>>,
 }
 
 /// bookshelf 使用 Terminal_NI 之规定
@@ -79,48 +113,67 @@ impl PinValidator {
             .keys()
             .cloned()
             .collect();
-        let by_pl: BTreeSet<String> = bookshelf
-            .pls
+        let by_pl: BTreeSet<String> = by_route
+            .iter()
+            .filter(|x| bookshelf.pls.get(x).is_some())
+            .cloned()
+            .collect();
+        let by_node: BTreeSet<String> = by_route
             .iter()
-            .filter(|x| matches!(x.r#type, crate::pl::Type::FixedNotInImage))
-            .map(|x| x.name.clone())
+            .filter(|x| bookshelf.nodes.is_terminal_ni(x))
+            .cloned()
             .collect();
-        let mut by_net: BTreeMap<String, i64> = BTreeMap::new();
+        let mut by_net: BTreeMap<String, Vec<String>> = BTreeMap::new();
         for net in bookshelf.nets.iter() {
             for net_pin in net.pin.iter() {
-                if let Some(instance_name) = by_route.get(&net_pin.instance_name) {
-                    let x = by_net.entry(instance_name.clone()).or_insert(0);
-                    *x += 1;
-                    if *x >= 2 {
-                        panic!("top_level_pin only has one port, however it has more than one");
-                    }
+                if by_route.contains(&net_pin.instance_name) {
+                    by_net
+                        .entry(net_pin.instance_name.clone())
+                        .or_default()
+                        .push(net.name.clone());
                 }
             }
         }
-        for (pin_name, count) in by_net.iter().filter(|(_, count)| **count > 1) {
-            info!("pin {} has {} port, which is illegal.", pin_name, count);
-        }
         Self {
             pin_in_route: by_route,
             pin_in_pl: by_pl,
+            pin_in_node: by_node,
             pin_in_net: by_net,
         }
     }
-    fn is_valid(&self) {
-        assert_eq!(self.pin_in_route.len(), self.pin_in_pl.len());
-        for name in self.pin_in_route.iter() {
-            assert!(self.pin_in_pl.get(name).is_some());
+
+    /// Every pin `.route` puts on a layer must be a placed `terminal_NI`
+    /// node; `Bookshelf::check_references` drops the others, so this is an
+    /// error. A pin has one port, so a pin on several nets keeps the first.
+    fn check(&self) -> anyhow::Result<()> {
+        let missing: Vec<&str> = self
+            .pin_in_route
+            .iter()
+            .filter(|x| !self.pin_in_pl.contains(*x) || !self.pin_in_node.contains(*x))
+            .map(|x| x.as_str())
+            .collect();
+        if !missing.is_empty() {
+            anyhow::bail!(
+                "{} terminal_NI pin(s) in .route are not placed terminal_NI nodes, e.g. {}",
+                missing.len(),
+                missing[..missing.len().min(5)].join(", ")
+            );
         }
-        assert!(self.pin_in_pl.len() >= self.pin_in_net.len());
-        for (pin_name, port_cnt) in self.pin_in_net.iter() {
-            if *port_cnt >= 2 {
-                panic!(
-                    "pin {} has more than one port. I think this is not valid",
-                    pin_name
-                );
-            }
+        let shared: Vec<String> = self
+            .pin_in_net
+            .iter()
+            .filter(|(_, nets)| nets.len() > 1)
+            .map(|(pin_name, nets)| format!("{} ({})", pin_name, nets.join(" ")))
+            .collect();
+        if !shared.is_empty() {
+            warn!(
+                "{} pin(s) are on more than one net, but have one port; kept the first net: {}",
+                shared.len(),
+                shared.join("; ")
+            );
         }
         info!("Passed pin validity test");
+        Ok(())
     }
 }
 pub struct DefPins(BTreeMap<String, DefPin>);
@@ -142,58 +195,96 @@ impl DefPins {
         res += "\nEND PINS";
         res
     }
-    pub fn build(bookshelf: &Bookshelf) -> Self {
-        PinValidator::build(bookshelf).is_valid();
+    pub fn build(bookshelf: &Bookshelf) -> anyhow::Result<Self> {
+        let validator = PinValidator::build(bookshelf);
+        validator.check()?;
         let mut res = BTreeMap::new();
-        let cnt = bookshelf.route.ni_terminal_len();
         for (name, layer_id) in bookshelf.route.ni_terminal_to_layer.iter() {
+            let (Some(pl), Some(node)) = (bookshelf.pls.get(name), bookshelf.nodes.get(name))
+            else {
+                continue;
+            };
             res.insert(
                 name.clone(),
                 DefPin {
                     name: name.clone(),
                     layer: format!("metal{}", *layer_id),
+                    orientation: pl.orientation,
+                    place: pl.place,
+                    shape: node.size,
                     ..Default::default()
                 },
             );
         }
-        let mut cnt_pl = 0;
-        for pl in bookshelf
-            .pls
-            .iter()
-            .filter(|x| matches!(x.r#type, crate::pl::Type::FixedNotInImage))
-        {
-            cnt_pl += 1;
-            let pin = res.get_mut(&pl.name).unwrap();
-            pin.place = pl.place;
-            pin.orientation = pl.orientation.clone();
-        }
-
-        let mut cnt_node = 0;
-        for node in bookshelf
-            .nodes
-            .iter()
-            .filter(|x| matches!(x.moveable, crate::nodes::Movable::FixedButOverlapAllowed))
-        {
-            cnt_node += 1;
-            let pin = res.get_mut(&node.name).unwrap();
-            pin.shape = node.size;
-        }
         for net in bookshelf.nets.iter() {
             for net_pin in net.pin.iter() {
-                if let Some(pin) = res.get_mut(&net_pin.instance_name) {
+                if let Some(pin) = res.get_mut(&net_pin.instance_name)
+                    && pin.net.is_none()
+                {
                     pin.net = Some(net.name.clone());
-                    pin.direction = Some(match net_pin.pin_name.as_bytes() {
-                        b"I" => "INPUT".to_string(),
-                        b"O" => "OUTPUT".to_string(),
-                        _ => panic!(
-                            "Terminal_NI considered as PIN which usually have only one direction, INPUT or OUTPUT"
-                        ),
-                    });
+                    pin.direction = Some(net_pin.direction);
                 }
             }
         }
-        assert_eq!(cnt, cnt_pl);
-        assert_eq!(cnt, cnt_node);
-        Self(res)
+        Ok(Self(res))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pin(orientation: Orient) -> DefPin {
+        DefPin {
+            name: "p".to_string(),
+            layer: "metal2".to_string(),
+            orientation,
+            place: Point { x: 10.0, y: 20.0 },
+            shape: Point { x: 2.0, y: 1.0 },
+            net: Some("n".to_string()),
+            direction: Some(PinDirection::Input),
+        }
+    }
+
+    #[test]
+    fn pins_keep_their_pl_orientation() {
+        let text = pin(Orient::N).write_to_string();
+        assert!(
+            text.contains("+ LAYER metal2 ( 0 0 ) ( 2000 1000 )"),
+            "{text}"
+        );
+        assert!(text.contains("+ FIXED ( 10000 20000 ) N ;"), "{text}");
+        let text = pin(Orient::W).write_to_string();
+        assert!(
+            text.contains("+ LAYER metal2 ( 0 -1000 ) ( 2000 0 )"),
+            "{text}"
+        );
+        assert!(text.contains("+ FIXED ( 10000 20000 ) W ;"), "{text}");
+    }
+
+    #[test]
+    fn turned_ports_cover_the_placed_terminal() {
+        let all = [
+            Orient::N,
+            Orient::W,
+            Orient::S,
+            Orient::E,
+            Orient::FN,
+            Orient::FE,
+            Orient::FS,
+            Orient::FW,
+        ];
+        for orientation in all {
+            let pin = pin(orientation);
+            let port = pin.port();
+            let (a, b) = (orientation.apply(port.ll), orientation.apply(port.ur));
+            let placed = (a.x.max(b.x) - a.x.min(b.x), a.y.max(b.y) - a.y.min(b.y));
+            let expected = match orientation.is_rotated() {
+                true => (1.0, 2.0),
+                false => (2.0, 1.0),
+            };
+            assert_eq!(placed, expected, "{orientation}");
+            assert_eq!((a.x.min(b.x), a.y.min(b.y)), (0.0, 0.0), "{orientation}");
+        }
     }
 }
@@ -1,4 +1,4 @@
-use crate::{geom::Point, parser::Bookshelf};
+use crate::{geom::Orient, lefdef::units::to_dbu, parser::Bookshelf, scl::RowOrientation};
 
 
 
@@ -23,22 +23,60 @@ impl Rows {
 
     pub fn write(&self) -> String {
         let mut res = String::new();
+        // Rows that do not give their orientation alternate FS/N so that
+        // neighbouring rows share rails; the others keep theirs.
+        let mut row_id = 0;
         for (iter, row) in self.rows.iter().enumerate() {
-            let orientation = match iter % 2 {
-                0 => "FS",
-                1 => "N",
-                _ => panic!("iter % 2 < 2"),
+            let orientation = match (row.site_orient, iter % 2) {
+                (Some(orient), _) => orient,
+                (None, 0) => Orient::FS,
+                (None, _) => Orient::N,
             };
-            res += &format!("\n ROW CORE_ROW_{} {} {} {} {} DO {} BY 1 STEP {} 0 ;", 
-                iter,
-                self.site_name,
-                row.subrow_origin * 1000,
-                row.coordinate * 1000,
-                orientation,
-                row.num_sites,
-                row.site_width * 1000,
-            );
+            let step = to_dbu(row.site_step());
+            for subrow in row.subrows.iter() {
+                let origin = row.subrow_rect(subrow).ll;
+                let repeat = match row.orientation {
+                    RowOrientation::Horizontal => format!("DO {} BY 1 STEP {} 0", subrow.num_sites, step),
+                    RowOrientation::Vertical => format!("DO 1 BY {} STEP 0 {}", subrow.num_sites, step),
+                };
+                res += &format!("\n ROW CORE_ROW_{} {} {} {} {} {} ;", 
+                    row_id,
+                    self.site_name,
+                    to_dbu(origin.x),
+                    to_dbu(origin.y),
+                    orientation,
+                    repeat,
+                );
+                row_id += 1;
+            }
         }
         res
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{diagnostics::ParseMode, io::test_util::read_fixture, scl::Scl};
+
+    fn orientations(name: &str, site_orient: &str) -> Vec<String> {
+        let row = |y| format!("CoreRow Horizontal\n Coordinate : {}\n Height : 12\n Sitewidth : 1\n \
+            Siteorient : {}\n SubrowOrigin : 0 NumSites : 10\nEnd\n", y, site_orient);
+        let text = format!("{}{}{}", row(0), row(12), row(24));
+        let (scl, _) = read_fixture(name, text.as_bytes(), ParseMode::Strict, Scl::read_from_file);
+        let bookshelf = Bookshelf { scl: scl.unwrap(), ..Default::default() };
+        Rows::build(&bookshelf, "CoreSite".to_string())
+            .write()
+            .lines()
+            .skip(1)
+            .map(|x| x.split_whitespace().nth(5).unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn rows_alternate_only_without_an_orientation() {
+        assert_eq!(orientations("legacy.scl", "1"), ["FS", "N", "FS"]);
+        assert_eq!(orientations("explicit_n.scl", "N"), ["N", "N", "N"]);
+        assert_eq!(orientations("explicit_fs.scl", "FS"), ["FS", "FS", "FS"]);
+    }
+}
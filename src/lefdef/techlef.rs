@@ -1,7 +1,11 @@
 use std::{io::Write, path::PathBuf};
 
 use crate::{
-    lefdef::writer::{CutLayer, OverlapLayer, RoutingLayer},
+    lefdef::{
+        options::Options,
+        units::{DBU_PER_MICRON, MANUFACTURING_GRID},
+        writer::{CutLayer, OverlapLayer, RoutingLayer},
+    },
     parser::Bookshelf,
 };
 
@@ -10,8 +14,8 @@ pub struct TechLef {
 }
 
 impl TechLef {
-    pub async fn build(bookshelf: &Bookshelf) -> anyhow::Result<Self> {
-        let routing_layers = RoutingLayer::build_routing_layers(bookshelf).await?;
+    pub async fn build(bookshelf: &Bookshelf, options: &Options) -> anyhow::Result<Self> {
+        let routing_layers = RoutingLayer::build_routing_layers(bookshelf, options).await?;
         Ok(Self {
             layer: routing_layers,
         })
@@ -24,17 +28,14 @@ impl TechLef {
             \nBUSBITCHARS \"[]\" ;\
             \nDIVIDERCHAR \"/\" ;\
             \nUNITS\
-            \n  DATABASE MICRONS 1000 ;\
+            \n  DATABASE MICRONS {} ;\
             \nEND UNITS\
-            \nMANUFACTURINGGRID 0.005 ;\
-            {}", OverlapLayer::format_a_default_one());
+            \nMANUFACTURINGGRID {} ;\
+            {}", DBU_PER_MICRON, MANUFACTURING_GRID, OverlapLayer::format_a_default_one());
         for (id, layer) in self.layer.iter().enumerate() {
-            res += &format!("{}", layer.format());
+            res += &layer.format();
             if id != self.layer.len() - 1 {
-                res += &format!(
-                    "{}",
-                    CutLayer::format_a_default_one(format!("CUT{}", id + 1))
-                );
+                res += &CutLayer::format_a_default_one(format!("CUT{}", id + 1));
             }
         }
         for (id, _) in self.layer.iter().skip(1).enumerate() {
@@ -50,7 +51,7 @@ impl TechLef {
                 \nEND V{}", 
                 id, id, id, id + 1, id);
         }
-        res += &format!("\nEND LIBRARY");
+        res += "\nEND LIBRARY";
         file.write_all(res.as_bytes()).unwrap();
     }
 }
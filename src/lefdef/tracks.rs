@@ -1,6 +1,6 @@
 use log::info;
 
-use crate::{geom::{Point, Rect}, parser::Bookshelf};
+use crate::{geom::{Point, Rect}, lefdef::{techlef::TechLef, units::to_dbu}, parser::Bookshelf};
 
 enum Direction {
     X,
@@ -45,9 +45,10 @@ impl Tracks {
         }
         res
     }
-    pub fn build(bookshelf: &Bookshelf) -> Self {
+    /// Tracks of every layer at its technology LEF pitch and offset, but for
+    /// pin-only layers.
+    pub fn build(bookshelf: &Bookshelf, techlef: &TechLef) -> anyhow::Result<Self> {
         let mut tracks = vec![];
-        let layer_count = bookshelf.route.min_wire_width.len();
         let die_area = {
             let ll = bookshelf.route.grid_origin;
             let ur = {
@@ -57,39 +58,22 @@ impl Tracks {
             };
             Rect { ll, ur }
         };
-        let core_area = {
-            let ll = {
-                let llx = bookshelf.scl.iter().map(|x| {
-                    x.subrow_origin
-                }).min().unwrap();
-                let lly = bookshelf.scl.iter().map(|x| {
-                    x.coordinate
-                }).min().unwrap();
-                Point {x: llx as f64, y: lly as f64}
-            };
-            let ur = {
-                let urx = bookshelf.scl.iter().map(|x| {
-                    x.subrow_origin + x.num_sites * x.site_width
-                }).max().unwrap();
-                let ury = bookshelf.scl.iter().map(|x| {
-                    x.coordinate + x.height
-                }).max().unwrap();
-                Point {
-                    x: urx as f64,
-                    y: ury as f64,
-                }
-            };
-            Rect { ll, ur }
+        let Some(core_area) = bookshelf.scl.bounding_box() else {
+            anyhow::bail!("cannot place tracks without rows in .scl");
         };
         info!("DIEAREA: {:?}", die_area);
         info!("COREAREA: {:?}", core_area);
-        for layer_id in 0..layer_count {
-            let pitch = bookshelf.route.min_wire_spacing[layer_id] * 1000 + bookshelf.route.min_wire_width[layer_id] * 1000;
+        for routing_layer in techlef.layer.iter().filter(|x| !x.is_pin_only()) {
+            let pitch = to_dbu(routing_layer.pitch());
+            let offset = to_dbu(routing_layer.offset());
+            if pitch <= 0 {
+                anyhow::bail!("{} has no pitch in database units", routing_layer.name());
+            }
             { // HANDLE X
-                let start = (die_area.ll.x as i64 * 1000) + pitch / 2;
-                let num_tracks = (die_area.ur.x as i64 * 1000 - start) / pitch;
+                let start = to_dbu(die_area.ll.x) + offset;
+                let num_tracks = (to_dbu(die_area.ur.x) - start) / pitch;
                 let step = pitch;
-                let layer = format!("metal{}", layer_id + 1);
+                let layer = routing_layer.name().to_string();
                 tracks.push(Track {
                     direction: Direction::X,
                     start,
@@ -100,10 +84,10 @@ impl Tracks {
             }
 
             { // HANDLE Y
-                let start = (die_area.ll.y as i64 * 1000) + pitch / 2;
-                let num_tracks = (die_area.ur.y as i64 * 1000 - start) / pitch;
+                let start = to_dbu(die_area.ll.y) + offset;
+                let num_tracks = (to_dbu(die_area.ur.y) - start) / pitch;
                 let step = pitch;
-                let layer = format!("metal{}", layer_id + 1);
+                let layer = routing_layer.name().to_string();
                 tracks.push(Track {
                     direction: Direction::Y,
                     start,
@@ -113,6 +97,6 @@ impl Tracks {
                 });
             }
         }
-        Self {tracks}
+        Ok(Self {tracks})
     }
 }
\ No newline at end of file
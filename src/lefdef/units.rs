@@ -0,0 +1,69 @@
+//! Bookshelf lengths are read as microns; DEF lengths are integer database
+//! units.
+
+/// `UNITS DISTANCE MICRONS` in DEF and `DATABASE MICRONS` in LEF.
+pub const DBU_PER_MICRON: i64 = 1000;
+
+/// `MANUFACTURINGGRID` of the technology LEF, in microns.
+pub const MANUFACTURING_GRID: f64 = 0.005;
+
+/// Convert a length in microns to the nearest database unit. Rounding, not
+/// truncating, keeps e.g. 0.3 (stored as 0.29999...) at 300, and keeps
+/// sub-micron placement that casting to an integer first would drop. Ties
+/// round away from zero, so negative lengths mirror positive ones.
+pub fn to_dbu(microns: f64) -> i64 {
+    (microns * DBU_PER_MICRON as f64).round() as i64
+}
+
+/// Round a length in microns to the nearest multiple of the manufacturing
+/// grid, going through database units so that it prints cleanly.
+pub fn snap_to_grid(microns: f64) -> f64 {
+    let grid = to_dbu(MANUFACTURING_GRID);
+    let dbu = (microns * DBU_PER_MICRON as f64 / grid as f64).round() as i64 * grid;
+    dbu as f64 / DBU_PER_MICRON as f64
+}
+
+/// Round a length in microns down to a multiple of the manufacturing grid,
+/// e.g. to keep a shape inside a cell whose edge is off the grid.
+pub fn floor_to_grid(microns: f64) -> f64 {
+    let grid = to_dbu(MANUFACTURING_GRID);
+    let dbu = to_dbu(microns).div_euclid(grid) * grid;
+    dbu as f64 / DBU_PER_MICRON as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dbu_round_to_nearest_with_ties_away_from_zero() {
+        assert_eq!(to_dbu(0.3), 300);
+        assert_eq!(to_dbu(-0.3), -300);
+        assert_eq!(to_dbu(12.0), 12000);
+        assert_eq!(to_dbu(-0.0004), 0);
+        // 62.5 and -62.5 units, exactly
+        assert_eq!(to_dbu(0.0625), 63);
+        assert_eq!(to_dbu(-0.0625), -63);
+    }
+
+    #[test]
+    fn grid_snaps_keep_values_on_the_grid() {
+        for microns in [0.0, 1.235, -1.235, 0.005, -0.005, 100.0] {
+            assert_eq!(snap_to_grid(microns), microns);
+            assert_eq!(floor_to_grid(microns), microns);
+        }
+    }
+
+    #[test]
+    fn grid_snaps_round_off_grid_values() {
+        // half a grid step, 2.5 units past 10 and -10
+        assert_eq!(snap_to_grid(0.0125), 0.015);
+        assert_eq!(snap_to_grid(-0.0125), -0.015);
+        assert_eq!(snap_to_grid(0.0124), 0.01);
+        assert_eq!(snap_to_grid(-0.0124), -0.01);
+        assert_eq!(floor_to_grid(0.0125), 0.01);
+        assert_eq!(floor_to_grid(0.009), 0.005);
+        assert_eq!(floor_to_grid(-0.0125), -0.015);
+        assert_eq!(floor_to_grid(-0.001), -0.005);
+    }
+}
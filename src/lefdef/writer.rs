@@ -1,40 +1,107 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
 use log::{info, warn};
 
-use crate::{geom::Point, lefdef::net::Node, parser::Bookshelf};
+use crate::{
+    geom::{Point, Rect, Symmetry},
+    lefdef::{
+        net::Node,
+        options::{LayerPolicy, Options, PorosityStrategy},
+        techlef::TechLef,
+        units::{DBU_PER_MICRON, MANUFACTURING_GRID, floor_to_grid, snap_to_grid, to_dbu},
+    },
+    nets::PinDirection,
+    nodes::Movable,
+    parser::Bookshelf,
+    scl::RowOrientation,
+};
 
 /// Pin has no size in ISPD 11.
 /// We give it a minimum size to make it useful.
 #[derive(Debug, Default)]
 pub struct Pin {
     pub name: String,
+    /// From the cell center, as in `.nets`.
     pub offset: Point,
-    pub direction: String,
+    pub direction: PinDirection,
+    pub layer: String,
+    /// The pin shape, from the lower left of the cell.
+    pub rect: Rect,
+    /// How far the shape was moved onto tracks.
+    pub displacement: Point,
+}
+
+/// Lower and upper edge of a pin shape `size` long around `center`, on the
+/// manufacturing grid and within `[0, len]`; the shape keeps its whole size,
+/// snapped to the grid. `None` when the cell is too small for it.
+fn fit_pin(center: f64, size: f64, len: f64) -> Option<(f64, f64)> {
+    let size = snap_to_grid(size);
+    if size > len {
+        return None;
+    }
+    // the last position on the grid that keeps the shape in the cell
+    let last = floor_to_grid(len - size);
+    let lo = snap_to_grid(center - size / 2.0).clamp(0.0, last);
+    Some((lo, snap_to_grid(lo + size)))
+}
+
+/// LEF macro `CLASS`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MacroClass {
+    /// A standard cell, placed on rows.
+    #[default]
+    Core,
+    /// A hard macro.
+    Block,
+    /// An IO pad, fixed outside the rows.
+    Pad,
+    /// A zero-area terminal, fixed but taking no room.
+    Cover,
+}
+
+impl std::fmt::Display for MacroClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MacroClass::Core => write!(f, "CORE"),
+            MacroClass::Block => write!(f, "BLOCK"),
+            MacroClass::Pad => write!(f, "PAD"),
+            MacroClass::Cover => write!(f, "COVER"),
+        }
+    }
+}
+
+/// Obstruction rectangles of a macro on one layer.
+#[derive(Debug, Default)]
+pub struct Obs {
+    pub layer: String,
+    pub rects: Vec<Rect>,
 }
 
 #[derive(Debug, Default)]
 pub struct Macro {
     pub name: String,
+    pub class: MacroClass,
     pub size: Point,
+    pub symmetry: Symmetry,
     pub pins: Vec<Pin>,
+    /// The footprint of a rectilinear macro on OVERLAP, from `.shapes`, and
+    /// the layers `.route` blocks over the macro.
+    pub obs: Vec<Obs>,
 }
 
 impl Macro {
     pub fn format_to_lef(&self) -> String {
         let mut res = format!(
             "\nMACRO {}\
-            \n  CLASS CORE ;\
+            \n  CLASS {} ;\
             \n  ORIGIN 0 0 ;\
             \n  SIZE {} BY {} ;\
-            \n  SYMMETRY X Y ;\
-            \n  SITE CoreSite ;",
-            self.name, self.size.x, self.size.y,
+            \n  SYMMETRY {} ;",
+            self.name, self.class, self.size.x, self.size.y, self.symmetry,
         );
-        let center = Point {
-            x: self.size.x / 2.0,
-            y: self.size.y / 2.0,
-        };
+        if self.class == MacroClass::Core {
+            res += "\n  SITE CoreSite ;";
+        }
         for pin in self.pins.iter() {
             res += &format!(
                 "\
@@ -42,30 +109,123 @@ impl Macro {
                 \n      DIRECTION {} ;\
                 \n      USE SIGNAL ; \
                 \n      PORT\
-                \n          LAYER metal1 ; \
+                \n          LAYER {} ; \
                 \n              RECT {} {} {} {} ;\
                 \n      END\
                 \n  END {}",
                 pin.name,
-                pin.direction,
-                pin.offset.x + center.x - 0.5,
-                pin.offset.y + center.y - 0.5,
-                pin.offset.x + center.x + 0.5,
-                pin.offset.y + center.y + 0.5,
+                pin.direction.lefdef_name(),
+                pin.layer,
+                pin.rect.ll.x,
+                pin.rect.ll.y,
+                pin.rect.ur.x,
+                pin.rect.ur.y,
                 pin.name,
             );
         }
+        if !self.obs.is_empty() {
+            res += "\n  OBS";
+            for obs in self.obs.iter() {
+                res += &format!("\n      LAYER {} ;", obs.layer);
+                for rect in obs.rects.iter() {
+                    res += &format!(
+                        "\n          RECT {} {} {} {} ;",
+                        rect.ll.x, rect.ll.y, rect.ur.x, rect.ur.y
+                    );
+                }
+            }
+            res += "\n  END";
+        }
         res += &format!("\n END {}", self.name);
         res
     }
+
+    /// Why the pins of this macro are not legal: pins larger than the cell,
+    /// and pins overlapping on one layer. `None` when they are.
+    fn pin_problems(&self, oversized: &[String]) -> Option<String> {
+        let mut problems = vec![];
+        if !oversized.is_empty() {
+            problems.push(format!("{} larger than the cell", oversized.join(" ")));
+        }
+        for (id, pin) in self.pins.iter().enumerate() {
+            for other in self.pins[id + 1..].iter() {
+                if pin.layer == other.layer && pin.rect.overlaps(&other.rect) {
+                    problems.push(format!("{} overlaps {}", pin.name, other.name));
+                }
+            }
+        }
+        match problems.is_empty() {
+            true => None,
+            false => Some(format!("{} ({})", self.name, problems.join(", "))),
+        }
+    }
+
+    /// What makes two nodes the same master: class, size, pins in order,
+    /// and OBS, in database units.
+    fn signature(&self) -> Signature {
+        let rect = |x: &Rect| {
+            [
+                to_dbu(x.ll.x),
+                to_dbu(x.ll.y),
+                to_dbu(x.ur.x),
+                to_dbu(x.ur.y),
+            ]
+        };
+        Signature {
+            class: self.class,
+            size: (to_dbu(self.size.x), to_dbu(self.size.y)),
+            pins: self
+                .pins
+                .iter()
+                .map(|x| {
+                    (
+                        to_dbu(x.offset.x),
+                        to_dbu(x.offset.y),
+                        x.direction,
+                        x.layer.clone(),
+                    )
+                })
+                .collect(),
+            obs: self
+                .obs
+                .iter()
+                .map(|x| (x.layer.clone(), x.rects.iter().map(rect).collect()))
+                .collect(),
+        }
+    }
+}
+
+/// Where a net meets a node: one pin per offset and net. Connections of
+/// different nets at one offset are not merged, as one pin on two nets would
+/// short them in DEF; they stay overlapping pins, reported as illegal.
+struct Connection {
+    offset: Point,
+    direction: PinDirection,
+    net: String,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct Signature {
+    class: MacroClass,
+    size: (i64, i64),
+    pins: Vec<(i64, i64, PinDirection, String)>,
+    obs: Vec<(String, Vec<[i64; 4]>)>,
 }
+
 #[derive(Debug, Default)]
 pub struct Macros {
+    /// Masters by their generated name.
     pub macros: BTreeMap<String, Macro>,
+    /// Node name to master name, for every node but `terminal_NI` pins.
+    pub node_to_master: BTreeMap<String, String>,
     pub net_to_nodes: BTreeMap<String, Vec<Node>>, // aux net name to pins
 }
 
 impl Macros {
+    pub fn master_of(&self, node_name: &str) -> Option<&Macro> {
+        self.macros.get(self.node_to_master.get(node_name)?)
+    }
+
     pub fn write_all(&self) -> String {
         let mut res = String::new();
         for r#macro in self.macros.values() {
@@ -73,62 +233,613 @@ impl Macros {
         }
         res
     }
-    pub async fn build_macro(bookshelf: &Bookshelf) -> anyhow::Result<Self> {
+    /// Class of `node_name`. Nodes no taller than a row are core cells when
+    /// movable or inside the rows, and taller movable nodes blocks; fixed
+    /// nodes with no area are covers, those off the rows pads, and the rest
+    /// blocks.
+    fn classify(bookshelf: &Bookshelf, node_name: &str) -> MacroClass {
+        let node = bookshelf.nodes.get(node_name).unwrap();
+        let fits_row = bookshelf
+            .scl
+            .iter()
+            .next()
+            .is_none_or(|row| node.size.y <= row.height);
+        let by_height = match fits_row {
+            true => MacroClass::Core,
+            false => MacroClass::Block,
+        };
+        if matches!(node.moveable, Movable::Movable) {
+            return by_height;
+        }
+        if node.size.x * node.size.y == 0.0 {
+            return MacroClass::Cover;
+        }
+        let (Some(core), Some(footprint)) =
+            (bookshelf.scl.bounding_box(), bookshelf.footprint(node_name))
+        else {
+            return MacroClass::Block;
+        };
+        if !footprint.iter().any(|x| x.overlaps(&core)) {
+            return MacroClass::Pad;
+        }
+        by_height
+    }
+
+    /// `SYMMETRY` of a macro of `size`. Bookshelf gives none, so every macro
+    /// may flip either way, as cells in alternating rows and mirrored blocks
+    /// are; a square one may also turn, which keeps its footprint.
+    fn symmetry(size: Point) -> Symmetry {
+        Symmetry {
+            r90: to_dbu(size.x) == to_dbu(size.y),
+            ..Symmetry::default()
+        }
+    }
+
+    /// Layer of the pins of `node_name`: metal1 for core cells, the lowest
+    /// layer `.route` does not block over the node for the others. `None`
+    /// when `.route` blocks every layer.
+    fn pin_layer(bookshelf: &Bookshelf, node_name: &str, class: MacroClass) -> Option<usize> {
+        let blocked = bookshelf.route.blockage_info.get(node_name);
+        match (class, blocked) {
+            (MacroClass::Core, _) | (_, None) => Some(1),
+            (_, Some(blocked)) => {
+                let num_layer = bookshelf.route.grid.num_layer.max(1);
+                let layer_id = (1..=num_layer).find(|x| !blocked.contains(x))?;
+                Some(layer_id as usize)
+            }
+        }
+    }
+
+    /// Shape of a pin at `offset` from the center of a `cell`: a square of
+    /// `size`, moved into the cell and snapped to the manufacturing grid,
+    /// or clipped to the cell when it does not fit, which makes it `false`.
+    /// A cell with no area has no inside, so its pins are squares around
+    /// their offsets.
+    fn pin_rect(offset: Point, size: f64, cell: Point) -> (Rect, bool) {
+        if cell.x * cell.y == 0.0 {
+            let half = snap_to_grid(size) / 2.0;
+            let center = Point {
+                x: offset.x + cell.x / 2.0,
+                y: offset.y + cell.y / 2.0,
+            };
+            let rect = Rect {
+                ll: Point {
+                    x: snap_to_grid(center.x - half),
+                    y: snap_to_grid(center.y - half),
+                },
+                ur: Point {
+                    x: snap_to_grid(center.x + half),
+                    y: snap_to_grid(center.y + half),
+                },
+            };
+            return (rect, true);
+        }
+        let x = fit_pin(offset.x + cell.x / 2.0, size, cell.x);
+        let y = fit_pin(offset.y + cell.y / 2.0, size, cell.y);
+        let fits = x.is_some() && y.is_some();
+        let (x, y) = (x.unwrap_or((0.0, cell.x)), y.unwrap_or((0.0, cell.y)));
+        let rect = Rect {
+            ll: Point { x: x.0, y: y.0 },
+            ur: Point { x: x.1, y: y.1 },
+        };
+        (rect, fits)
+    }
+
+    /// Log how far `Options::snap_pins` moved each master pin, and warn about
+    /// the pins in `not_on_track` it could not move, the layers in `off_grid`
+    /// it could not snap to, and the nodes in `off_track` whose snapped pins
+    /// miss the tracks as placed.
+    fn report_snapping(
+        &self,
+        not_on_track: &[String],
+        off_grid: &BTreeSet<&str>,
+        off_track: &[&str],
+    ) {
+        let mut moved = String::new();
+        let (mut num_moved, mut max_move) = (0, 0.0_f64);
+        for r#macro in self.macros.values() {
+            for pin in r#macro.pins.iter() {
+                let Point { x, y } = pin.displacement;
+                if x == 0.0 && y == 0.0 {
+                    continue;
+                }
+                num_moved += 1;
+                max_move = max_move.max(x.abs() + y.abs());
+                moved += &format!(
+                    "\n    {} {} by ( {:.3} {:.3} )",
+                    r#macro.name, pin.name, x, y
+                );
+            }
+        }
+        info!(
+            "Moved {} master pin(s) onto tracks, at most {:.3} apart:{}",
+            num_moved, max_move, moved
+        );
+        if !not_on_track.is_empty() {
+            warn!(
+                "{} master(s) have pins no track crossing nearby could take: {}",
+                not_on_track.len(),
+                not_on_track.join("; ")
+            );
+        }
+        if !off_grid.is_empty() {
+            warn!(
+                "Pins on {} were left in place: their pitch does not divide the site step and row height",
+                off_grid.iter().copied().collect::<Vec<_>>().join(", ")
+            );
+        }
+        if !off_track.is_empty() {
+            warn!(
+                "{} node(s) have snapped pins off the tracks as placed, being flipped or off the site grid of the first row, e.g. {}",
+                off_track.len(),
+                off_track[..off_track.len().min(5)].join(", ")
+            );
+        }
+    }
+
+    /// Where the tracks `Tracks::build` writes for `layer` cross, as seen
+    /// from a node on the site grid of the first row: a crossing in node
+    /// coordinates. It is the same for every node on that grid only if the
+    /// pitch divides the site step and the row height; `None` otherwise.
+    fn track_phase(bookshelf: &Bookshelf, layer: &RoutingLayer) -> Option<Point> {
+        let row = bookshelf.scl.iter().next()?;
+        let site = row.subrow_rect(row.subrows.first()?).ll;
+        let step = match row.orientation {
+            RowOrientation::Horizontal => (row.site_step(), row.height),
+            RowOrientation::Vertical => (row.height, row.site_step()),
+        };
+        let pitch = to_dbu(layer.pitch());
+        let divides = |len: f64| pitch > 0 && to_dbu(len) % pitch == 0;
+        if !divides(step.0) || !divides(step.1) {
+            return None;
+        }
+        let origin = bookshelf.route.grid_origin;
+        let phase = |origin: f64, site: f64| {
+            let track = to_dbu(origin) + to_dbu(layer.offset());
+            (track - to_dbu(site)).rem_euclid(pitch) as f64 / DBU_PER_MICRON as f64
+        };
+        Some(Point {
+            x: phase(origin.x, site.x),
+            y: phase(origin.y, site.y),
+        })
+    }
+
+    /// Whether the pin shape `rect` of `node_name` is centered on a crossing
+    /// of the tracks of `layer`, as the node is placed.
+    fn is_on_track(
+        bookshelf: &Bookshelf,
+        node_name: &str,
+        layer: &RoutingLayer,
+        rect: Rect,
+        cell: Point,
+    ) -> bool {
+        let local = Point {
+            x: rect.center().x - cell.x / 2.0,
+            y: rect.center().y - cell.y / 2.0,
+        };
+        let Some(chip) = bookshelf.pin_position(node_name, local) else {
+            return false;
+        };
+        let origin = bookshelf.route.grid_origin;
+        let pitch = to_dbu(layer.pitch());
+        let on = |at: f64, origin: f64| {
+            (to_dbu(at) - to_dbu(origin) - to_dbu(layer.offset())).rem_euclid(pitch) == 0
+        };
+        on(chip.x, origin.x) && on(chip.y, origin.y)
+    }
+
+    /// Move `rect`, a pin shape in `cell`, so that its center sits on a
+    /// crossing of tracks `pitch` apart through `phase`, in cell coordinates.
+    /// Takes the nearest crossing, within two pitches, that keeps the shape
+    /// in `cell` and `spacing` away from the shapes in `placed`. `None` when
+    /// there is no such crossing.
+    fn snap_pin(
+        rect: Rect,
+        cell: Point,
+        pitch: f64,
+        phase: Point,
+        spacing: f64,
+        placed: &[Rect],
+    ) -> Option<Rect> {
+        let step = to_dbu(pitch);
+        if step <= 0 {
+            return None;
+        }
+        // tracks nearest to `at` first, in microns
+        let tracks = |at: f64, phase: f64| {
+            let start = to_dbu(phase);
+            let k = ((to_dbu(at) - start) as f64 / step as f64).round() as i64;
+            let mut res: Vec<f64> = (k - 2..=k + 2)
+                .map(|k| (start + k * step) as f64 / DBU_PER_MICRON as f64)
+                .collect();
+            res.sort_by(|a, b| (a - at).abs().total_cmp(&(b - at).abs()));
+            res
+        };
+        let center = rect.center();
+        let ys = tracks(center.y, phase.y);
+        let mut centers: Vec<Point> = tracks(center.x, phase.x)
+            .into_iter()
+            .flat_map(|x| ys.iter().map(move |y| Point { x, y: *y }))
+            .collect();
+        let distance = |p: &Point| (p.x - center.x).abs() + (p.y - center.y).abs();
+        centers.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+        let half = Point {
+            x: rect.width() / 2.0,
+            y: rect.height() / 2.0,
+        };
+        centers.into_iter().find_map(|center| {
+            let res = Rect {
+                ll: Point {
+                    x: snap_to_grid(center.x - half.x),
+                    y: snap_to_grid(center.y - half.y),
+                },
+                ur: Point {
+                    x: snap_to_grid(center.x + half.x),
+                    y: snap_to_grid(center.y + half.y),
+                },
+            };
+            let inside =
+                res.ll.x >= 0.0 && res.ll.y >= 0.0 && res.ur.x <= cell.x && res.ur.y <= cell.y;
+            let apart = placed.iter().all(|x| {
+                let keep_out = Rect {
+                    ll: Point {
+                        x: x.ll.x - spacing,
+                        y: x.ll.y - spacing,
+                    },
+                    ur: Point {
+                        x: x.ur.x + spacing,
+                        y: x.ur.y + spacing,
+                    },
+                };
+                !keep_out.overlaps(&res)
+            });
+            (inside && apart).then_some(res)
+        })
+    }
+
+    /// OBS of node `name`: its `.shapes` footprint on OVERLAP, and the
+    /// footprint (or its bounding box when it has no shapes) on each layer
+    /// it blocks according to `.route`, unless porosity is written to DEF,
+    /// which [`Self::build_macro`] warns about.
+    /// The footprint is snapped to the manufacturing grid, as moving it into
+    /// the cell leaves float noise.
+    fn build_obs(bookshelf: &Bookshelf, options: &Options, name: &str, size: Point) -> Vec<Obs> {
+        let mut res = vec![];
+        let footprint = bookshelf.macro_shape(name).map(|rects| {
+            rects
+                .iter()
+                .map(|rect| {
+                    rect.map_corners(|p| Point {
+                        x: snap_to_grid(p.x),
+                        y: snap_to_grid(p.y),
+                    })
+                })
+                .collect::<Vec<_>>()
+        });
+        if let Some(rects) = footprint.as_ref() {
+            res.push(Obs {
+                layer: "OVERLAP".to_string(),
+                rects: rects.clone(),
+            });
+        }
+        if options.porosity != PorosityStrategy::Obs {
+            return res;
+        }
+        if let Some(layer_ids) = bookshelf.route.blockage_info.get(name) {
+            let rects = footprint.unwrap_or_else(|| {
+                vec![Rect {
+                    ll: Point::default(),
+                    ur: size,
+                }]
+            });
+            let num_layer = bookshelf.route.grid.num_layer;
+            for layer_id in layer_ids {
+                if !(1..=num_layer).contains(layer_id) {
+                    warn!(
+                        "{} is blocked on layer {}, but there are only {} layers; ignored",
+                        name, layer_id, num_layer
+                    );
+                    continue;
+                }
+                res.push(Obs {
+                    layer: format!("metal{}", layer_id),
+                    rects: rects.clone(),
+                });
+            }
+        }
+        res
+    }
+
+    pub async fn build_macro(
+        bookshelf: &Bookshelf,
+        techlef: &TechLef,
+        options: &Options,
+    ) -> anyhow::Result<Self> {
         warn!(
             "Notification for MACRO!!!\
             \n  Usually, bookshelf does not provide enough information for a macro. I filled it freely.\
             \n  list to say:\
-            \n  * SYMMETRY is set to X and Y by default.\
+            \n  * SYMMETRY is set to X and Y, and R90 for square cells.\
             \n  * SITE is set to core by default.\
-            \n  * PIN NAME is set freely with a random suffix.\
+            \n  * PIN NAME is the direction and a counter, by direction and offset.\
             \n  * PORT is set to layer1 for standard cells, as ISPD official required.\
-            \n  * PORT SHAPE are 1x1 as we do not check up `bookshelf.masterpin`, i am tired of writing this code.\
-            \n  * PORT DIRECTION is specified according to `bookshelf.net` file."
+            \n  * PORT SHAPE is a square as wide as the layer's min wire width (or --pin-size), moved into the cell.\
+            \n  * PORT DIRECTION is specified according to `bookshelf.net` file, INOUT when it is not given."
         );
+        let num_blocked = bookshelf.route.blockage_info.len();
+        if options.porosity != PorosityStrategy::Obs && num_blocked > 0 {
+            warn!(
+                "{} node(s) blocked in .route get no routing OBS; --porosity {} models their blockage in DEF BLOCKAGES instead",
+                num_blocked, options.porosity
+            );
+        }
         let mut res = Self::default();
         info!("Building macros...");
-        bookshelf.nodes.nodes.iter().for_each(|(_, node)| {
-            res.macros.entry(node.name.clone()).or_insert(Macro {
-                name: node.name.clone(),
-                size: node.size,
-                pins: vec![],
-            });
-        });
-        bookshelf.nets.iter().for_each(|net| {
-            net.pin.iter().for_each(|pin| {
-                let r#macro = res.macros.get_mut(&pin.instance_name).unwrap();
-                let pin_id = r#macro.pins.len();
-                let pin_name = format!("{}_{}", pin.pin_name, pin_id);
-                r#macro.pins.push(Pin {
-                    name: pin_name.clone(),
-                    offset: pin.offset,
-                    direction: match pin.pin_name.as_str() {
-                        "I" => "INPUT".to_string(),
-                        "O" => "OUTPUT".to_string(),
-                        _ => panic!("Unable to translate direction"),
+        // one macro per node first, named after it
+        let mut instances = BTreeMap::new();
+        bookshelf
+            .nodes
+            .iter()
+            .filter(|node| !bookshelf.nodes.is_terminal_ni(&node.name))
+            .for_each(|node| {
+                instances.insert(
+                    node.name.clone(),
+                    Macro {
+                        name: node.name.clone(),
+                        class: Self::classify(bookshelf, &node.name),
+                        size: node.size,
+                        symmetry: Self::symmetry(node.size),
+                        pins: vec![],
+                        obs: Self::build_obs(bookshelf, options, &node.name, node.size),
                     },
-                });
-                let nodes_in_net = res.net_to_nodes.entry(net.name.clone()).or_insert(vec![]);
-
-                if bookshelf.nodes.is_terminal_ni(&pin.instance_name) {
-                    nodes_in_net.push(Node::Pin(pin.instance_name.clone()));
-                } else {
-                    nodes_in_net.push(Node::InstancePin(pin.instance_name.clone(), pin_name));
+                );
+            });
+        // connections of a net at one offset of a node make one pin; other
+        // nets at that offset get pins of their own, see `Connection`
+        let mut connections: BTreeMap<&str, Vec<Connection>> = BTreeMap::new();
+        let mut net_pins: Vec<(&str, &str, Option<usize>)> = vec![];
+        let mut num_shared = 0;
+        for net in bookshelf.nets.iter() {
+            let net_start = net_pins.len();
+            for pin in net.pin.iter() {
+                let instance_name = pin.instance_name.as_str();
+                if bookshelf.nodes.is_terminal_ni(instance_name) {
+                    net_pins.push((&net.name, instance_name, None));
+                    continue;
                 }
+                let offset = pin.offset.unwrap_or_default();
+                let at_offset = |x: &Connection| {
+                    to_dbu(x.offset.x) == to_dbu(offset.x) && to_dbu(x.offset.y) == to_dbu(offset.y)
+                };
+                let node_connections = connections.entry(instance_name).or_default();
+                let found = node_connections
+                    .iter()
+                    .position(|x| at_offset(x) && x.net == net.name);
+                let id = match found {
+                    Some(id) => {
+                        let connection = &mut node_connections[id];
+                        connection.direction = connection.direction.merge(pin.direction);
+                        if net_pins[net_start..].contains(&(&net.name, instance_name, Some(id))) {
+                            continue;
+                        }
+                        id
+                    }
+                    None => {
+                        if node_connections.iter().any(at_offset) {
+                            num_shared += 1;
+                        }
+                        node_connections.push(Connection {
+                            offset,
+                            direction: pin.direction,
+                            net: net.name.clone(),
+                        });
+                        node_connections.len() - 1
+                    }
+                };
+                net_pins.push((&net.name, instance_name, Some(id)));
+            }
+        }
+        if num_shared > 0 {
+            warn!(
+                "{} connection(s) sit at the offset of a pin on another net; kept as separate pins, as one pin would short the nets",
+                num_shared
+            );
+        }
+        // pins ordered by direction, then offset, are named per direction
+        let mut pin_names: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        let mut oversized: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        let mut unsnapped: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        let mut buried = vec![];
+        let mut outside = vec![];
+        // masters share pins, so they snap the same way wherever placed
+        let phases: Vec<Option<Point>> = techlef
+            .layer
+            .iter()
+            .map(|layer| Self::track_phase(bookshelf, layer))
+            .collect();
+        let mut off_grid = BTreeSet::new();
+        let mut off_track = vec![];
+        for (instance_name, node_connections) in connections.iter() {
+            let r#macro = instances.get_mut(*instance_name).unwrap();
+            let no_area = r#macro.size.x * r#macro.size.y == 0.0;
+            if no_area {
+                outside.push(*instance_name);
+            }
+            let mut order: Vec<usize> = (0..node_connections.len()).collect();
+            order.sort_by_key(|id| {
+                let connection = &node_connections[*id];
+                (
+                    connection.direction,
+                    to_dbu(connection.offset.x),
+                    to_dbu(connection.offset.y),
+                )
             });
-        });
-        info!("Finished building macros");
+            let mut names = vec![String::new(); node_connections.len()];
+            let mut num_direction: BTreeMap<PinDirection, usize> = BTreeMap::new();
+            let layer_id = match Self::pin_layer(bookshelf, instance_name, r#macro.class) {
+                Some(layer_id) => layer_id,
+                None => {
+                    buried.push(*instance_name);
+                    bookshelf.route.grid.num_layer.max(1) as usize
+                }
+            };
+            let pin_size = options.pin_size.unwrap_or_else(|| {
+                let min_width = bookshelf.route.min_wire_width.get(layer_id - 1);
+                min_width.copied().unwrap_or(1.0)
+            });
+            let pin_size = snap_to_grid(pin_size).max(MANUFACTURING_GRID);
+            let tracks = match techlef.layer.get(layer_id - 1) {
+                Some(layer) if options.snap_pins && !no_area && !layer.is_pin_only() => {
+                    match phases[layer_id - 1] {
+                        Some(phase) => Some((layer, phase)),
+                        None => {
+                            off_grid.insert(layer.name());
+                            None
+                        }
+                    }
+                }
+                _ => None,
+            };
+            let spacing = bookshelf.route.min_wire_spacing.get(layer_id - 1);
+            let spacing = spacing.copied().unwrap_or_default();
+            let mut placed = vec![];
+            for id in order {
+                let connection = &node_connections[id];
+                let count = num_direction.entry(connection.direction).or_default();
+                names[id] = format!("{}_{}", connection.direction, count);
+                *count += 1;
+                let (mut rect, fits) = Self::pin_rect(connection.offset, pin_size, r#macro.size);
+                if !fits {
+                    oversized
+                        .entry(instance_name)
+                        .or_default()
+                        .push(names[id].clone());
+                }
+                let mut displacement = Point::default();
+                if let Some((layer, phase)) = tracks
+                    && fits
+                {
+                    let cell = r#macro.size;
+                    match Self::snap_pin(rect, cell, layer.pitch(), phase, spacing, &placed) {
+                        Some(snapped) => {
+                            if !Self::is_on_track(bookshelf, instance_name, layer, snapped, cell)
+                                && off_track.last() != Some(instance_name)
+                            {
+                                off_track.push(*instance_name);
+                            }
+                            displacement = Point {
+                                x: snapped.center().x - rect.center().x,
+                                y: snapped.center().y - rect.center().y,
+                            };
+                            rect = snapped;
+                        }
+                        None => unsnapped
+                            .entry(instance_name)
+                            .or_default()
+                            .push(names[id].clone()),
+                    }
+                }
+                placed.push(rect);
+                r#macro.pins.push(Pin {
+                    name: names[id].clone(),
+                    offset: connection.offset,
+                    direction: connection.direction,
+                    layer: format!("metal{}", layer_id),
+                    rect,
+                    displacement,
+                });
+            }
+            pin_names.insert(instance_name, names);
+        }
+        if !outside.is_empty() {
+            warn!(
+                "{} node(s) with pins have no area, so their pin shapes are squares around the pin offsets, outside the cell, e.g. {}",
+                outside.len(),
+                outside[..outside.len().min(5)].join(", ")
+            );
+        }
+        if !buried.is_empty() {
+            warn!(
+                "{} node(s) are blocked on every layer, so their pins sit under OBS on the top layer, e.g. {}",
+                buried.len(),
+                buried[..buried.len().min(5)].join(", ")
+            );
+        }
+        for (net_name, instance_name, id) in net_pins {
+            let node = match id {
+                None => Node::Pin(instance_name.to_string()),
+                Some(id) => Node::InstancePin(
+                    instance_name.to_string(),
+                    pin_names[instance_name][id].clone(),
+                ),
+            };
+            res.net_to_nodes
+                .entry(net_name.to_string())
+                .or_default()
+                .push(node);
+        }
+        // then share one master among nodes with the same signature; their
+        // pins are named the same way, so instance pin names are master ones
+        let mut masters: HashMap<Signature, String> = HashMap::new();
+        let num_instances = instances.len();
+        for (node_name, mut r#macro) in instances {
+            let master_name = masters
+                .entry(r#macro.signature())
+                .or_insert_with(|| {
+                    let name = format!("cell_{}", res.macros.len());
+                    r#macro.name = name.clone();
+                    res.macros.insert(name.clone(), r#macro);
+                    name
+                })
+                .clone();
+            res.node_to_master.insert(node_name, master_name);
+        }
+        // nodes of one master share its pins, so check each master once
+        let mut illegal = vec![];
+        let mut not_on_track = vec![];
+        let mut checked = HashSet::new();
+        for (node_name, master_name) in res.node_to_master.iter() {
+            if !checked.insert(master_name) {
+                continue;
+            }
+            if let Some(pins) = unsnapped.get(node_name.as_str()) {
+                not_on_track.push(format!("{} ({})", master_name, pins.join(" ")));
+            }
+            let oversized = oversized.get(node_name.as_str());
+            let r#macro = &res.macros[master_name];
+            if let Some(problem) = r#macro.pin_problems(oversized.map_or(&[], |x| x)) {
+                illegal.push(problem);
+            }
+        }
+        if !illegal.is_empty() {
+            warn!(
+                "{} master(s) have pins that could not be legalized: {}",
+                illegal.len(),
+                illegal.join("; ")
+            );
+        }
+        if options.snap_pins {
+            res.report_snapping(&not_on_track, &off_grid, &off_track);
+        }
+        let mut num_class: BTreeMap<String, usize> = BTreeMap::new();
+        for r#macro in res.macros.values() {
+            *num_class.entry(r#macro.class.to_string()).or_default() += 1;
+        }
+        let num_class: Vec<String> = num_class
+            .iter()
+            .map(|(class, num)| format!("{} {}", num, class))
+            .collect();
+        info!(
+            "Finished building macros: {} masters ({}) for {} nodes",
+            res.macros.len(),
+            num_class.join(", "),
+            num_instances
+        );
         Ok(res)
     }
 }
 
-#[derive(Default)]
-enum LayerType {
-    #[default]
-    Routing,
-}
-
-#[derive(Default)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 enum Direction {
     #[default]
     Horizontal,
@@ -138,8 +849,8 @@ enum Direction {
 impl std::fmt::Display for Direction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Direction::Horizontal => write!(f, "HORIZONTAL"),
-            Direction::Vertical => write!(f, "VERTICAL"),
+            Direction::Horizontal => f.pad("HORIZONTAL"),
+            Direction::Vertical => f.pad("VERTICAL"),
         }
     }
 }
@@ -152,6 +863,8 @@ pub struct RoutingLayer {
     offset: f64,
     width: f64,
     spacing: f64,
+    /// No tracks in DEF, see `LayerPolicy::PinOnly`.
+    pin_only: bool,
 }
 
 #[derive(Default)]
@@ -186,52 +899,157 @@ impl OverlapLayer {
 }
 
 impl RoutingLayer {
-    pub async fn build_routing_layers(bookshelf: &Bookshelf) -> anyhow::Result<Vec<Self>> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn pitch(&self) -> f64 {
+        self.pitch
+    }
+
+    /// Of the first track from the die origin.
+    pub fn offset(&self) -> f64 {
+        self.offset
+    }
+
+    pub fn is_vertical(&self) -> bool {
+        matches!(self.direction, Direction::Vertical)
+    }
+
+    pub fn is_pin_only(&self) -> bool {
+        self.pin_only
+    }
+
+    /// Direction of a layer with `vertical` and `horizontal` capacity, and
+    /// why, when the capacities do not make it plain. `below` is the
+    /// direction of the layer underneath, if any.
+    fn direction(
+        vertical: i64,
+        horizontal: i64,
+        below: Option<Direction>,
+        policy: LayerPolicy,
+    ) -> (Direction, Option<&'static str>) {
+        let alternate = match below {
+            Some(Direction::Horizontal) => Direction::Vertical,
+            _ => Direction::Horizontal,
+        };
+        match (vertical > 0, horizontal > 0) {
+            (false, true) => (Direction::Horizontal, None),
+            (true, false) => (Direction::Vertical, None),
+            (false, false) => (alternate, Some("no capacity")),
+            (true, true) => {
+                let direction = match policy {
+                    LayerPolicy::Dominant if vertical > horizontal => Direction::Vertical,
+                    LayerPolicy::Dominant if horizontal > vertical => Direction::Horizontal,
+                    _ => alternate,
+                };
+                (direction, Some("capacity in both directions"))
+            }
+        }
+    }
+
+    /// Pitch that fits `wanted` tracks in `tile_len`, snapped down to the
+    /// manufacturing grid, but no less than the wire and spacing need.
+    fn capacity_pitch(name: &str, tile_len: f64, wanted: f64, min_pitch: f64) -> f64 {
+        let pitch = (tile_len / wanted / MANUFACTURING_GRID + 1e-9).floor() * MANUFACTURING_GRID;
+        if pitch < min_pitch {
+            warn!(
+                "{} needs pitch {} for its capacity, below width plus spacing {}; kept the latter",
+                name, pitch, min_pitch
+            );
+            return min_pitch;
+        }
+        pitch
+    }
+
+    /// One layer per `.route` layer. The pitch is width plus spacing, or,
+    /// with `options.capacity_pitch`, whatever fits as many tracks in a
+    /// tile as the `.route` capacity allows; ISPD 2011 counts capacity as
+    /// tracks times width plus spacing. Layers with capacity in both
+    /// directions or in neither follow `options.layer_policy`.
+    pub async fn build_routing_layers(
+        bookshelf: &Bookshelf,
+        options: &Options,
+    ) -> anyhow::Result<Vec<Self>> {
         let mut res = vec![];
+        let mut table = String::new();
+        let mut over_capacity = vec![];
         let aux_layer = &bookshelf.route;
         let num_layer = aux_layer.vertical_capacity.len();
         for layer_id in 0..num_layer {
             let layer_name = format!("metal{}", layer_id + 1);
             let vertical_cap = bookshelf.route.vertical_capacity[layer_id];
             let horizontal_cap = bookshelf.route.horizontal_capacity[layer_id];
-            let direction = match (vertical_cap > 0, horizontal_cap > 0) {
-                (false, false) => Direction::Horizontal, // M1, usually horizontal.
-                (false, true) => Direction::Horizontal,  // horizontal
-                (true, false) => Direction::Vertical,    // vertical
-                (true, true) => {
-                    panic!("I don't know how to parse a bidirectional lef");
-                }
-            };
+            let below = res.last().map(|x: &Self| x.direction);
+            let (direction, ambiguity) =
+                Self::direction(vertical_cap, horizontal_cap, below, options.layer_policy);
+            let pin_only = ambiguity.is_some() && options.layer_policy == LayerPolicy::PinOnly;
+            if let Some(why) = ambiguity {
+                info!(
+                    "{} has {} (vertical {}, horizontal {}); made it {}{} by the {} layer policy",
+                    layer_name,
+                    why,
+                    vertical_cap,
+                    horizontal_cap,
+                    direction,
+                    if pin_only { " without tracks" } else { "" },
+                    options.layer_policy
+                );
+            }
             let min_wire_width = bookshelf.route.min_wire_width[layer_id];
             let min_wire_spacing = bookshelf.route.min_wire_spacing[layer_id];
-            let pitch = min_wire_width + min_wire_spacing;
-            {
-                // LEFDEF does not support partial routing blockage.
-                let cap_restriction = vertical_cap.max(horizontal_cap);
-                let tile_len = match direction {
-                    Direction::Horizontal => bookshelf.route.tile_size.y,
-                    Direction::Vertical => bookshelf.route.tile_size.x,
-                };
-                // we must prove (tile_len / (min_width + spacing)) > cap_restriction.
-                if (cap_restriction as f64) < (tile_len / pitch as f64) {
-                    warn!(
-                        "we OVERLOOK capacity restriction. on layer {}\
-                        \n  this restrication can not be translated into LEFDEF soundly.
-                        \n  if WIDTH and SPACING already satisfied, nothing happens.
-                        \n  otherwise you will see this note.",
-                        layer_id + 1
-                    );
-                }
+            let min_pitch = min_wire_width + min_wire_spacing;
+            let capacity = match direction {
+                Direction::Horizontal => horizontal_cap,
+                Direction::Vertical => vertical_cap,
+            };
+            // tracks run along the layer, so they stack across the tile
+            let tile_len = match direction {
+                Direction::Horizontal => bookshelf.route.tile_size.y,
+                Direction::Vertical => bookshelf.route.tile_size.x,
+            };
+            let wanted = capacity as f64 / min_pitch;
+            let pitch = match options.capacity_pitch && capacity > 0 {
+                true => Self::capacity_pitch(&layer_name, tile_len, wanted, min_pitch),
+                false => min_pitch,
+            };
+            let tracks = match pin_only {
+                true => 0.0,
+                false => (tile_len / pitch + 1e-9).floor(),
+            };
+            let ratio = match capacity {
+                0 => "-".to_string(),
+                _ => format!("{:.3}", tracks / wanted),
+            };
+            if capacity > 0 && tracks > wanted.floor() {
+                over_capacity.push(layer_name.clone());
             }
+            table += &format!(
+                "\n    {:<8} {:<10} {:>8} {:>8.2} {:>8} {:>8} {:>7}",
+                layer_name, direction, capacity, wanted, tracks, pitch, ratio
+            );
             res.push(Self {
                 name: layer_name,
-                offset: pitch as f64 / 2.0,
+                offset: pitch / 2.0,
                 direction,
-                pitch: pitch as f64,
-                width: min_wire_width as f64,
-                spacing: min_wire_spacing as f64,
+                pitch,
+                width: min_wire_width,
+                spacing: min_wire_spacing,
+                pin_only,
             })
         }
+        info!(
+            "Routing layers, per tile:\
+            \n    {:<8} {:<10} {:>8} {:>8} {:>8} {:>8} {:>7}{}",
+            "layer", "direction", "capacity", "wanted", "tracks", "pitch", "ratio", table
+        );
+        if !over_capacity.is_empty() {
+            warn!(
+                "{} fit more tracks in a tile than the .route capacity allows; \
+                pass --capacity-pitch to widen their pitch",
+                over_capacity.join(", ")
+            );
+        }
         Ok(res)
     }
     pub fn format(&self) -> String {
@@ -250,3 +1068,300 @@ impl RoutingLayer {
         res
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::test_util::{RECTILINEAR, read_bookshelf};
+
+    #[test]
+    fn fit_pin_keeps_the_size_inside_the_cell() {
+        assert_eq!(fit_pin(1.0, 0.5, 2.0), Some((0.75, 1.25)));
+        assert_eq!(fit_pin(0.1, 0.5, 2.0), Some((0.0, 0.5)));
+        assert_eq!(fit_pin(1.9, 0.5, 2.0), Some((1.5, 2.0)));
+        // the cell edge is off the grid: the shape stays whole, inside it
+        assert_eq!(fit_pin(1.0, 0.5, 1.003), Some((0.5, 1.0)));
+        assert_eq!(fit_pin(0.8, 0.5, 1.004), Some((0.5, 1.0)));
+        // off-grid centers and sizes land on the grid
+        assert_eq!(fit_pin(1.0012, 0.2013, 2.0), Some((0.9, 1.1)));
+        assert_eq!(fit_pin(1.0, 0.5, 0.5), Some((0.0, 0.5)));
+        assert_eq!(fit_pin(1.0, 0.5, 0.498), None);
+    }
+
+    #[test]
+    fn pin_rect_clips_oversized_pins() {
+        let cell = Point { x: 2.0, y: 0.4 };
+        let (rect, fits) = Macros::pin_rect(Point { x: 0.5, y: 0.0 }, 0.5, cell);
+        assert!(!fits);
+        assert_eq!((rect.ll.x, rect.ur.x), (1.25, 1.75));
+        assert_eq!((rect.ll.y, rect.ur.y), (0.0, 0.4));
+    }
+
+    #[test]
+    fn pin_rect_without_area_centers_on_the_offset() {
+        let (rect, fits) = Macros::pin_rect(Point { x: 0.3, y: -1.0 }, 0.5, Point::default());
+        assert!(fits);
+        assert_eq!((rect.ll.x, rect.ll.y), (0.05, -1.25));
+        assert_eq!((rect.ur.x, rect.ur.y), (0.55, -0.75));
+    }
+
+    fn square(x: f64, y: f64, half: f64) -> Rect {
+        Rect {
+            ll: Point {
+                x: x - half,
+                y: y - half,
+            },
+            ur: Point {
+                x: x + half,
+                y: y + half,
+            },
+        }
+    }
+
+    #[test]
+    fn snap_pin_takes_the_nearest_free_crossing_in_the_cell() {
+        let cell = Point { x: 4.0, y: 2.0 };
+        let phase = Point { x: 0.5, y: 0.5 };
+        let snap = |rect, placed: &[Rect]| Macros::snap_pin(rect, cell, 1.0, phase, 0.1, placed);
+        let rect = square(1.2, 0.7, 0.25);
+        assert_eq!(snap(rect, &[]), Some(square(1.5, 0.5, 0.25)));
+        let taken = square(1.5, 0.5, 0.25);
+        assert_eq!(snap(rect, &[taken]), Some(square(0.5, 0.5, 0.25)));
+        // crossings at the cell edge would push the shape out of the cell
+        let cell = Point { x: 0.6, y: 0.6 };
+        let phase = Point::default();
+        assert_eq!(Macros::snap_pin(rect, cell, 1.0, phase, 0.1, &[]), None);
+    }
+
+    #[test]
+    fn rectilinear_macros_get_their_footprint_on_overlap() {
+        let bookshelf = read_bookshelf("overlap", &RECTILINEAR);
+        let size = Point { x: 4.0, y: 4.0 };
+        let r#macro = Macro {
+            name: "m".to_string(),
+            obs: Macros::build_obs(&bookshelf, &Options::default(), "m", size),
+            ..Default::default()
+        };
+        assert!(r#macro.format_to_lef().ends_with(
+            "\n  OBS\
+            \n      LAYER OVERLAP ;\
+            \n          RECT 0 0 4 2 ;\
+            \n          RECT 0 2 2 4 ;\
+            \n  END\
+            \n END m"
+        ));
+    }
+
+    #[test]
+    fn blocked_layers_get_obs_unless_porosity_goes_to_def() {
+        let route: &[u8] = b"route 1.0\nGrid : 4 4 3\nNumBlockageNodes : 1\nm 3 1 2 5\n";
+        let files = [RECTILINEAR.as_slice(), &[("route", route)]].concat();
+        let bookshelf = read_bookshelf("blocked", &files);
+        let size = Point { x: 4.0, y: 4.0 };
+        let layers = |porosity| {
+            let options = Options {
+                porosity,
+                ..Default::default()
+            };
+            Macros::build_obs(&bookshelf, &options, "m", size)
+                .into_iter()
+                .map(|x| (x.layer, x.rects.len()))
+                .collect::<Vec<_>>()
+        };
+        let footprint = |layer: &str| (layer.to_string(), 2);
+        assert_eq!(
+            layers(PorosityStrategy::Obs),
+            [
+                footprint("OVERLAP"),
+                footprint("metal1"),
+                footprint("metal2")
+            ]
+        );
+        assert_eq!(layers(PorosityStrategy::Striped), [footprint("OVERLAP")]);
+    }
+
+    #[test]
+    fn layer_direction_follows_capacity_then_policy() {
+        use Direction::*;
+        let direction = |vertical, horizontal, below, policy| {
+            let (direction, why) = RoutingLayer::direction(vertical, horizontal, below, policy);
+            (direction, why.is_some())
+        };
+        for policy in [
+            LayerPolicy::Dominant,
+            LayerPolicy::Alternate,
+            LayerPolicy::PinOnly,
+        ] {
+            // capacity one way is plain, whatever is below
+            assert_eq!(direction(1, 0, Some(Vertical), policy), (Vertical, false));
+            assert_eq!(
+                direction(0, 1, Some(Horizontal), policy),
+                (Horizontal, false)
+            );
+            // no capacity alternates, starting horizontal
+            assert_eq!(direction(0, 0, None, policy), (Horizontal, true));
+            assert_eq!(direction(0, 0, Some(Horizontal), policy), (Vertical, true));
+            assert_eq!(direction(0, 0, Some(Vertical), policy), (Horizontal, true));
+            // a tie is no dominant direction
+            assert_eq!(direction(5, 5, Some(Horizontal), policy), (Vertical, true));
+        }
+        let dominant = LayerPolicy::Dominant;
+        assert_eq!(direction(6, 5, Some(Vertical), dominant), (Vertical, true));
+        assert_eq!(
+            direction(5, 6, Some(Horizontal), dominant),
+            (Horizontal, true)
+        );
+        let alternate = LayerPolicy::Alternate;
+        assert_eq!(
+            direction(6, 5, Some(Vertical), alternate),
+            (Horizontal, true)
+        );
+        assert_eq!(direction(5, 6, None, alternate), (Horizontal, true));
+    }
+
+    #[test]
+    fn classes_split_at_the_row_height_and_the_row_edges() {
+        let nodes: &[u8] = b"a 1 12\nb 1 12.005\n\
+            c 2 12 terminal\nd 4 12.005 terminal\ne 0 3 terminal\n\
+            f 4 4 terminal\ng 4 4 terminal\nh 4 4 terminal\n";
+        let pl: &[u8] = b"a 0 0 : N\nb 0 0 : N\n\
+            c 10 0 : N /FIXED\nd 20 0 : N /FIXED\ne 5 5 : N /FIXED\n\
+            f 100 0 : N /FIXED\ng 98 10 : N /FIXED\nh 50 -4 : N /FIXED\n";
+        let scl: &[u8] = b"CoreRow Horizontal\n Coordinate : 0\n Height : 12\n Sitewidth : 1\n\
+            SubrowOrigin : 0 NumSites : 100\nEnd\n";
+        let classes = |bookshelf: &Bookshelf| {
+            bookshelf
+                .nodes
+                .iter()
+                .map(|x| Macros::classify(bookshelf, &x.name).to_string())
+                .collect::<Vec<_>>()
+        };
+        let bookshelf = read_bookshelf("classes", &[("nodes", nodes), ("pl", pl), ("scl", scl)]);
+        assert_eq!(
+            classes(&bookshelf),
+            [
+                "CORE", "BLOCK", "CORE", "BLOCK", "COVER", "PAD", "CORE", "PAD"
+            ]
+        );
+        // without rows, nothing is too tall and no fixed node is in the rows
+        let bookshelf = read_bookshelf("classes_without_rows", &[("nodes", nodes), ("pl", pl)]);
+        assert_eq!(
+            classes(&bookshelf),
+            [
+                "CORE", "CORE", "BLOCK", "BLOCK", "COVER", "BLOCK", "BLOCK", "BLOCK"
+            ]
+        );
+    }
+
+    #[test]
+    fn capacity_pitch_snaps_down_but_not_below_the_minimum() {
+        let pitch = |tile_len, wanted, min_pitch| {
+            RoutingLayer::capacity_pitch("metal1", tile_len, wanted, min_pitch)
+        };
+        assert_eq!(pitch(10.0, 4.0, 0.2), 2.5);
+        assert_eq!(pitch(10.0, 3.0, 0.2), 666.0 * MANUFACTURING_GRID);
+        // exact multiples of the grid do not lose a step to rounding
+        assert_eq!(pitch(0.3, 2.0, 0.1), 30.0 * MANUFACTURING_GRID);
+        assert_eq!(pitch(1.0, 10.0, 0.3), 0.3);
+    }
+
+    async fn build_macros(name: &str, nodes: &[u8], nets: &[u8]) -> Macros {
+        let bookshelf = read_bookshelf(name, &[("nodes", nodes), ("nets", nets)]);
+        let techlef = TechLef { layer: vec![] };
+        Macros::build_macro(&bookshelf, &techlef, &Options::default())
+            .await
+            .unwrap()
+    }
+
+    fn pin_names(macros: &Macros, net_name: &str) -> Vec<String> {
+        macros.net_to_nodes[net_name]
+            .iter()
+            .map(|node| match node {
+                Node::Pin(name) => name.clone(),
+                Node::InstancePin(instance, pin) => format!("{}/{}", instance, pin),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn nodes_with_the_same_pins_share_a_master() {
+        let macros = build_macros(
+            "shared",
+            b"UCLA nodes 1.0\na 4 4\nb 4 4\nc 4 4\nd 6 4\np 0 0 terminal_NI\n",
+            b"UCLA nets 1.0\n\
+            NetDegree : 4 n1\na I : 1 0\nb I : 1 0\nc I : 1 0\nd I : 1 0\n\
+            NetDegree : 4 n2\na O : -1 0\nb O : -1 0\nc O : 0 1\np I\n",
+        )
+        .await;
+        let masters: Vec<&str> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|x| macros.node_to_master[*x].as_str())
+            .collect();
+        assert_eq!(masters, ["cell_0", "cell_0", "cell_1", "cell_2"]);
+        assert_eq!(macros.macros.len(), 3);
+        assert!(!macros.node_to_master.contains_key("p"));
+        let pins: Vec<&str> = macros.macros["cell_0"]
+            .pins
+            .iter()
+            .map(|x| x.name.as_str())
+            .collect();
+        assert_eq!(pins, ["I_0", "O_0"]);
+        assert_eq!(macros.macros["cell_0"].symmetry.to_string(), "X Y R90");
+        assert_eq!(macros.macros["cell_2"].symmetry.to_string(), "X Y");
+        assert_eq!(
+            pin_names(&macros, "n1"),
+            ["a/I_0", "b/I_0", "c/I_0", "d/I_0"]
+        );
+        assert_eq!(pin_names(&macros, "n2"), ["a/O_0", "b/O_0", "c/O_0", "p"]);
+    }
+
+    #[tokio::test]
+    async fn connections_of_one_net_at_one_offset_make_one_pin() {
+        let macros = build_macros(
+            "merged",
+            b"UCLA nodes 1.0\na 4 4\nb 4 4\n",
+            b"UCLA nets 1.0\n\
+            NetDegree : 3 n\na I : 1 0\na O : 1 0\nb I\n\
+            NetDegree : 2 m\na : 1 0\nb O : -1 1\n",
+        )
+        .await;
+        let pins: Vec<(&str, PinDirection)> = macros
+            .master_of("a")
+            .unwrap()
+            .pins
+            .iter()
+            .map(|x| (x.name.as_str(), x.direction))
+            .collect();
+        assert_eq!(
+            pins,
+            [
+                ("B_0", PinDirection::Bidirectional),
+                ("U_0", PinDirection::Unknown)
+            ]
+        );
+        assert_eq!(pin_names(&macros, "n"), ["a/B_0", "b/I_0"]);
+        assert_eq!(pin_names(&macros, "m"), ["a/U_0", "b/O_0"]);
+    }
+
+    #[tokio::test]
+    async fn nets_at_one_offset_keep_overlapping_pins() {
+        let macros = build_macros(
+            "overlapping",
+            b"UCLA nodes 1.0\na 4 4\nb 4 4\n",
+            b"UCLA nets 1.0\n\
+            NetDegree : 2 n\na I : 1 0\nb O\n\
+            NetDegree : 2 m\na O : 1 0\nb I\n",
+        )
+        .await;
+        let a = macros.master_of("a").unwrap();
+        let pins: Vec<(&str, Rect)> = a.pins.iter().map(|x| (x.name.as_str(), x.rect)).collect();
+        assert_eq!(pins.len(), 2);
+        assert_eq!(pins[0].1, pins[1].1);
+        assert_eq!(pin_names(&macros, "n"), ["a/I_0", "b/O_0"]);
+        assert_eq!(pin_names(&macros, "m"), ["a/O_0", "b/I_0"]);
+        assert_eq!(
+            a.pin_problems(&[]).unwrap(),
+            format!("{} (I_0 overlaps O_0)", a.name)
+        );
+    }
+}
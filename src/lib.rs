@@ -1,4 +1,6 @@
 pub mod aux;
+pub mod diagnostics;
+pub mod flavor;
 pub mod geom;
 pub mod io;
 pub mod lefdef;
@@ -9,5 +11,4 @@ pub mod pl;
 pub mod route;
 pub mod scl;
 pub mod shape;
-pub mod util;
 pub mod wts;
@@ -1,50 +1,138 @@
-use std::{fs::File, io::BufReader, path::PathBuf};
+use std::{fmt, path::PathBuf};
 
-use crate::{geom::Point, io::reader::TokenReader};
+use crate::{
+    diagnostics::Diagnostics,
+    geom::Point,
+    io::reader::{CommonReader, FromToken, Keyword},
+};
+
+/// Ordered as pins are listed on generated masters.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PinDirection {
+    Input,
+    Output,
+    Bidirectional,
+    /// The `.nets` line gives no direction.
+    #[default]
+    Unknown,
+}
+
+impl PinDirection {
+    /// `DIRECTION` in LEF and DEF. Pins of unknown direction are `INOUT`,
+    /// which is the only choice that is never wrong.
+    pub fn lefdef_name(&self) -> &'static str {
+        match self {
+            PinDirection::Input => "INPUT",
+            PinDirection::Output => "OUTPUT",
+            PinDirection::Bidirectional | PinDirection::Unknown => "INOUT",
+        }
+    }
+
+    /// Direction of one pin that is both `self` and `other`.
+    pub fn merge(self, other: Self) -> Self {
+        match (self, other) {
+            (x, y) if x == y => x,
+            (PinDirection::Unknown, x) | (x, PinDirection::Unknown) => x,
+            _ => PinDirection::Bidirectional,
+        }
+    }
+}
+
+impl FromToken for PinDirection {
+    fn from_token(token: &[u8]) -> Option<Self> {
+        match Keyword::new(token).as_bytes() {
+            b"I" => Some(PinDirection::Input),
+            b"O" => Some(PinDirection::Output),
+            b"B" => Some(PinDirection::Bidirectional),
+            _ => None,
+        }
+    }
+}
+
+/// The bookshelf letter, `U` when unknown.
+impl fmt::Display for PinDirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let letter = match self {
+            PinDirection::Input => "I",
+            PinDirection::Output => "O",
+            PinDirection::Bidirectional => "B",
+            PinDirection::Unknown => "U",
+        };
+        write!(f, "{}", letter)
+    }
+}
 
 #[derive(Default, Clone, Debug)]
 pub struct Pin {
-    pub pin_name: String,
+    pub direction: PinDirection,
     pub instance_name: String,
-    pub offset: Point,
+    /// From the node center; some flavors leave it out.
+    pub offset: Option<Point>,
 }
 #[derive(Default, Clone, Debug)]
 pub struct Net {
     pub name: String,
+    /// `NetDegree` as declared, which should equal the number of pins.
+    pub degree: usize,
     pub pin: Vec<Pin>,
 }
 
 #[derive(Default, Clone, Debug)]
 pub struct Nets {
     nets: Vec<Net>,
+    /// `NumNets` as declared in the header.
+    pub num_nets: Option<usize>,
+    /// `NumPins` as declared in the header.
+    pub num_pins: Option<usize>,
 }
 
 impl Pin {
-    pub async fn read(reader: &mut TokenReader<BufReader<File>>) -> anyhow::Result<Self> {
-        let mut res = Self::default();
-        res.instance_name = reader.next_token()?.unwrap().to_string();
-        res.pin_name = reader.next_token()?.unwrap().to_string();
-        assert_eq!(b":", reader.next_token()?.unwrap().as_bytes());
-        res.offset = Point::read(reader).await?;
-        Ok(res)
+    /// `<instance> [I|O|B] [: <x> <y>]`, on one line: a pin without
+    /// direction followed by a pin on a node named `I`, `O` or `B` stays two
+    /// pins.
+    pub fn read(reader: &mut CommonReader) -> anyhow::Result<Self> {
+        let instance_name = reader.next_string("pin instance name")?;
+        reader.read_line(|reader| {
+            let direction = match reader.peek_token()?.and_then(PinDirection::from_token) {
+                Some(direction) => {
+                    reader.next_token()?;
+                    direction
+                }
+                None => PinDirection::Unknown,
+            };
+            let offset = match reader.peek_token()? {
+                Some(b":") => {
+                    reader.next_token()?;
+                    Some(Point::read(reader)?)
+                }
+                _ => None,
+            };
+            Ok(Self {
+                direction,
+                instance_name,
+                offset,
+            })
+        })
     }
 }
 
 impl Net {
-    pub async fn read(reader: &mut TokenReader<BufReader<File>>) -> anyhow::Result<Self> {
+    pub fn read(reader: &mut CommonReader) -> anyhow::Result<Self> {
         let mut res = Self::default();
-        assert_eq!(b"NetDegree", reader.next_token()?.unwrap().as_bytes());
-        assert_eq!(":", reader.next_token()?.unwrap());
-        let _ = reader.next_token()?.unwrap().as_bytes(); // number;
-        let net_name = reader.next_token()?.unwrap().to_string();
-        res.name = net_name;
+        reader.read_line(|reader| {
+            reader.expect("NetDegree")?;
+            reader.expect(":")?;
+            res.degree = reader.next_parse::<usize>("net degree")?;
+            res.name = reader.next_string("net name")?;
+            Ok(())
+        })?;
         while let Some(token) = reader.peek_token()? {
-            match token.to_uppercase().as_bytes() {
+            match Keyword::new(token).as_bytes() {
                 b"NETDEGREE" => {
                     break;
                 }
                 _ => {
-                    let pin = Pin::read(reader).await.unwrap();
+                    let pin = Pin::read(reader)?;
                     res.pin.push(pin);
                 }
             }
@@ -60,23 +148,176 @@ impl Nets {
     pub fn iter(&self) -> std::slice::Iter<'_, Net> {
         self.nets.iter()
     }
-    pub async fn read_from_file(file_path: PathBuf) -> anyhow::Result<Self> {
+    pub fn is_empty(&self) -> bool {
+        self.nets.is_empty()
+    }
+    /// Keep the pins `keep` accepts, given their net name. `NetDegree` stays
+    /// as declared.
+    pub fn retain_pins(&mut self, mut keep: impl FnMut(&str, &Pin) -> bool) {
+        for net in self.nets.iter_mut() {
+            net.pin.retain(|pin| keep(&net.name, pin));
+        }
+    }
+    pub fn num_pins_read(&self) -> usize {
+        self.nets.iter().map(|x| x.pin.len()).sum()
+    }
+    pub fn read_from_file(
+        file_path: PathBuf,
+        diagnostics: &mut Diagnostics,
+    ) -> anyhow::Result<Self> {
         let mut res = Nets::default();
-        let mut reader = crate::io::reader::TokenReader::new_from_path(&file_path);
+        let mut reader = CommonReader::new_from_path(&file_path)?;
+        let mut num_nets_at = String::new();
+        let mut num_pins_at = String::new();
         while let Some(token) = reader.peek_token()? {
-            match token.to_ascii_uppercase().as_bytes() {
-                b"UCLA" | b"#" | b"NUMNETS" | b"NUMPINS" => {
-                    reader.swallow_line()?;
+            match Keyword::new(token).as_bytes() {
+                b"NUMNETS" => {
+                    reader.next_token()?;
+                    num_nets_at = reader.location();
+                    res.num_nets = reader.header_count("number of nets", diagnostics)?;
+                }
+                b"NUMPINS" => {
+                    reader.next_token()?;
+                    num_pins_at = reader.location();
+                    res.num_pins = reader.header_count("number of pins", diagnostics)?;
                 }
                 b"NETDEGREE" => {
-                    let net = Net::read(&mut reader).await?;
-                    res.nets.push(net);
+                    let line = reader.line();
+                    match Net::read(&mut reader) {
+                        Ok(net) => {
+                            if net.degree != net.pin.len() {
+                                diagnostics.warn(format!(
+                                    "{}:{}: NetDegree of net {} declares {}, but {} were read",
+                                    file_path.display(),
+                                    line,
+                                    net.name,
+                                    net.degree,
+                                    net.pin.len()
+                                ));
+                            }
+                            res.nets.push(net)
+                        }
+                        Err(e) => {
+                            diagnostics.recover(e)?;
+                            reader.skip_until("NetDegree")?;
+                        }
+                    }
                 }
                 _ => {
-                    println!("Unexpected token");
+                    let found = token.to_vec();
+                    diagnostics.recover(reader.error("`NetDegree`", Some(&found)).into())?;
+                    reader.swallow_line()?;
                 }
             }
         }
+        if let Some(declared) = res.num_nets {
+            diagnostics.check_count(&num_nets_at, "NumNets", declared, res.len());
+        }
+        if let Some(declared) = res.num_pins {
+            let read = res.num_pins_read();
+            diagnostics.check_count(&num_pins_at, "NumPins", declared, read);
+        }
         Ok(res)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        diagnostics::ParseMode,
+        io::test_util::{after_name, read_fixture},
+    };
+
+    fn read(name: &str, content: &[u8], mode: ParseMode) -> (Nets, Vec<String>) {
+        let (nets, messages) = read_fixture(name, content, mode, |path, diagnostics| {
+            Nets::read_from_file(path.to_path_buf(), diagnostics)
+        });
+        (nets.unwrap(), messages)
+    }
+
+    #[test]
+    fn direction_stays_on_its_line() {
+        let (nets, _) = read(
+            "direction.nets",
+            b"UCLA nets 1.0\nNetDegree : 3 n\na\nB I : 1 2\nO\n",
+            ParseMode::Strict,
+        );
+        let net = nets.iter().next().unwrap();
+        let pins: Vec<(&str, PinDirection)> = net
+            .pin
+            .iter()
+            .map(|pin| (pin.instance_name.as_str(), pin.direction))
+            .collect();
+        assert_eq!(
+            pins,
+            [
+                ("a", PinDirection::Unknown),
+                ("B", PinDirection::Input),
+                ("O", PinDirection::Unknown)
+            ]
+        );
+        assert!(net.pin[0].offset.is_none());
+        assert_eq!(net.pin[1].offset.unwrap().y, 2.0);
+    }
+
+    #[test]
+    fn offset_stays_on_its_line() {
+        let (nets, messages) = read(
+            "offset.nets",
+            b"NetDegree : 1 n\na I :\n1 2\n",
+            ParseMode::Lenient,
+        );
+        assert!(nets.is_empty());
+        assert!(messages[0].ends_with(":2:5: expected x coordinate, found end of line"));
+    }
+
+    #[test]
+    fn num_nets_mismatch_is_a_warning() {
+        let text = b"NumNets : 2\nNetDegree : 1 n\na I\n";
+        for mode in [ParseMode::Strict, ParseMode::Lenient] {
+            let (nets, messages) = read("num_nets.nets", text, mode);
+            assert_eq!(nets.len(), 1);
+            assert_eq!(
+                after_name(&messages, "num_nets.nets"),
+                [":1: NumNets declares 2, but 1 were read"]
+            );
+        }
+    }
+
+    #[test]
+    fn num_pins_mismatch_is_a_warning() {
+        let text = b"NumPins : 3\nNetDegree : 1 n\na I\nNetDegree : 1 m\nb O\n";
+        for mode in [ParseMode::Strict, ParseMode::Lenient] {
+            let (_, messages) = read("num_pins.nets", text, mode);
+            assert_eq!(
+                after_name(&messages, "num_pins.nets"),
+                [":1: NumPins declares 3, but 2 were read"]
+            );
+        }
+    }
+
+    #[test]
+    fn net_degree_mismatch_is_a_warning() {
+        let text = b"NetDegree : 3 n\na I\nb O\nNetDegree : 1 m\nc I\n";
+        for mode in [ParseMode::Strict, ParseMode::Lenient] {
+            let (nets, messages) = read("degree.nets", text, mode);
+            assert_eq!(nets.len(), 2);
+            assert_eq!(
+                after_name(&messages, "degree.nets"),
+                [":1: NetDegree of net n declares 3, but 2 were read"]
+            );
+        }
+    }
+
+    #[test]
+    fn merged_directions() {
+        use PinDirection::*;
+        assert_eq!(Input.merge(Input), Input);
+        assert_eq!(Unknown.merge(Output), Output);
+        assert_eq!(Input.merge(Unknown), Input);
+        assert_eq!(Input.merge(Output), Bidirectional);
+        assert_eq!(Bidirectional.merge(Input), Bidirectional);
+        assert_eq!(Unknown.merge(Unknown), Unknown);
+    }
+}
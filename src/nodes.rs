@@ -1,13 +1,21 @@
 use std::{
     collections::{BTreeMap, btree_map::Values},
-    path::PathBuf,
+    path::Path,
 };
 
-use crate::{geom::Point, io::reader};
+use crate::{
+    diagnostics::Diagnostics,
+    geom::Point,
+    io::reader::{self, CommonReader, Keyword},
+};
 
 #[derive(Default)]
 pub struct Nodes {
     pub nodes: BTreeMap<String, Node>,
+    /// `NumNodes` as declared in the header.
+    pub num_nodes: Option<usize>,
+    /// `NumTerminals` as declared in the header.
+    pub num_terminals: Option<usize>,
 }
 
 #[derive(Debug, Default)]
@@ -33,56 +41,91 @@ impl Nodes {
         self.nodes.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
     pub fn get(&self, name: &str) -> Option<&Node> {
         self.nodes.get(name)
     }
 
+    /// Nodes marked `terminal` or `terminal_NI`.
+    pub fn num_terminals_read(&self) -> usize {
+        self.iter()
+            .filter(|x| !matches!(x.moveable, Movable::Movable))
+            .count()
+    }
+
+    /// False for unknown nodes.
     pub fn is_terminal_ni(&self, name: &str) -> bool {
         matches!(
-            self.nodes.get(name).unwrap().moveable,
-            Movable::FixedButOverlapAllowed
+            self.nodes.get(name).map(|x| &x.moveable),
+            Some(Movable::FixedButOverlapAllowed)
         )
     }
-    pub async fn read(path: &PathBuf) -> anyhow::Result<Self> {
-        let mut reader = reader::TokenReader::new_from_path(path);
-        let mut ret = Self {
-            nodes: BTreeMap::new(),
-        };
+    fn read_size(reader: &mut CommonReader) -> anyhow::Result<Point> {
+        let x = reader.next_parse::<f64>("node width")?;
+        let y = reader.next_parse::<f64>("node height")?;
+        Ok(Point { x, y })
+    }
+
+    pub fn read(path: &Path, diagnostics: &mut Diagnostics) -> anyhow::Result<Self> {
+        let mut reader = reader::TokenReader::new_from_path(path)?;
+        let mut ret = Self::default();
         let mut last_node_name = String::new();
+        let mut num_nodes_at = String::new();
+        let mut num_terminals_at = String::new();
         while let Some(token) = reader.next_token()? {
-            match token.as_bytes() {
-                b"#" | b"UCLA" | b"NumNodes" | b"NumTerminals" => {
-                    let _ = reader.swallow_line();
+            match Keyword::new(token).as_bytes() {
+                b"NUMNODES" => {
+                    num_nodes_at = reader.location();
+                    ret.num_nodes = reader.header_count("number of nodes", diagnostics)?;
                 }
-                b"terminal" => {
-                    ret.nodes.get_mut(&last_node_name).unwrap().moveable = Movable::Fixed;
+                b"NUMTERMINALS" => {
+                    num_terminals_at = reader.location();
+                    ret.num_terminals = reader.header_count("number of terminals", diagnostics)?;
                 }
-                b"terminal_NI" => {
-                    ret.nodes.get_mut(&last_node_name).unwrap().moveable =
-                        Movable::FixedButOverlapAllowed;
+                keyword @ (b"TERMINAL" | b"TERMINAL_NI") => {
+                    let moveable = match keyword {
+                        b"TERMINAL" => Movable::Fixed,
+                        _ => Movable::FixedButOverlapAllowed,
+                    };
+                    match ret.nodes.get_mut(&last_node_name) {
+                        Some(node) => node.moveable = moveable,
+                        None => {
+                            let found = token.to_vec();
+                            diagnostics.recover(reader.error("node name", Some(&found)).into())?;
+                        }
+                    }
                 }
                 _ => {
-                    let name = token.to_string();
-                    let x = reader
-                        .next_token()?
-                        .map(str::parse::<i64>)
-                        .unwrap()
-                        .unwrap() as f64;
-                    let y = reader
-                        .next_token()?
-                        .map(str::parse::<i64>)
-                        .unwrap()
-                        .unwrap() as f64;
+                    let name = String::from_utf8_lossy(token).into_owned();
+                    let size = match reader.read_line(Self::read_size) {
+                        Ok(size) => size,
+                        Err(e) => {
+                            diagnostics.recover(e)?;
+                            last_node_name.clear();
+                            reader.swallow_line()?;
+                            continue;
+                        }
+                    };
                     last_node_name = name.clone();
                     let next_node = Node {
                         name: name.clone(),
-                        size: Point { x, y },
+                        size,
                         moveable: Movable::Movable,
                     };
                     ret.nodes.insert(name, next_node);
                 }
             }
         }
+        if let Some(declared) = ret.num_nodes {
+            diagnostics.check_count(&num_nodes_at, "NumNodes", declared, ret.len());
+        }
+        if let Some(declared) = ret.num_terminals {
+            let read = ret.num_terminals_read();
+            diagnostics.check_count(&num_terminals_at, "NumTerminals", declared, read);
+        }
         Ok(ret)
     }
 
@@ -92,3 +135,51 @@ impl Nodes {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        diagnostics::ParseMode,
+        io::test_util::{after_name, read_fixture},
+    };
+
+    #[test]
+    fn num_nodes_mismatch_is_a_warning() {
+        let text = b"UCLA nodes 1.0\nNumNodes : 3\na 1 1\nb 1 1\n";
+        for mode in [ParseMode::Strict, ParseMode::Lenient] {
+            let (nodes, messages) = read_fixture("count.nodes", text, mode, Nodes::read);
+            assert_eq!(nodes.unwrap().len(), 2);
+            assert_eq!(
+                after_name(&messages, "count.nodes"),
+                [":2: NumNodes declares 3, but 2 were read"]
+            );
+        }
+        let text = b"NumNodes : 2\na 1 1\nb x 1\n";
+        let (nodes, _) = read_fixture("skipped.nodes", text, ParseMode::Strict, Nodes::read);
+        assert!(nodes.is_err());
+        let (nodes, messages) =
+            read_fixture("skipped.nodes", text, ParseMode::Lenient, Nodes::read);
+        assert_eq!(nodes.unwrap().len(), 1);
+        assert_eq!(
+            after_name(&messages, "skipped.nodes"),
+            [
+                ":3:3: expected node width, found `x`",
+                ":1: NumNodes declares 2, but 1 were read"
+            ]
+        );
+    }
+
+    #[test]
+    fn num_terminals_mismatch_is_a_warning() {
+        let text = b"NumTerminals : 2\na 1 1\np 0 0 terminal\n";
+        for mode in [ParseMode::Strict, ParseMode::Lenient] {
+            let (nodes, messages) = read_fixture("terminals.nodes", text, mode, Nodes::read);
+            assert_eq!(nodes.unwrap().num_terminals_read(), 1);
+            assert_eq!(
+                after_name(&messages, "terminals.nodes"),
+                [":1: NumTerminals declares 2, but 1 were read"]
+            );
+        }
+    }
+}
@@ -1,4 +1,25 @@
-use crate::{aux::Aux, nets::Nets, nodes::Nodes, pl::Pls, route::Route, scl::Scl, shape::Shapes};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+use tokio::task::JoinHandle;
+
+use crate::{
+    aux::Aux,
+    diagnostics::{Diagnostic, Diagnostics, ParseMode, Severity},
+    flavor::Flavor,
+    geom::{Orient, Point, Rect},
+    io::source::Unpacked,
+    nets::Nets,
+    nodes::{Movable, Nodes},
+    pl::Pls,
+    route::Route,
+    scl::Scl,
+    shape::Shapes,
+    wts::Wts,
+};
 
 #[derive(Default)]
 pub struct Bookshelf {
@@ -9,32 +30,116 @@ pub struct Bookshelf {
     pub scl: Scl,
     pub route: Route,
     pub shape: Shapes,
+    pub wts: Wts,
+    pub flavor: Flavor,
+    /// Records skipped in lenient mode and warnings, in reading order.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// How [`Bookshelf::build_from_aux`] reads a benchmark.
+pub struct ReadOptions {
+    pub mode: ParseMode,
+    /// Take this flavor instead of detecting it.
+    pub flavor: Option<Flavor>,
+    /// Routing layers to make up when there is no `.route`.
+    pub default_layers: usize,
+    /// Pitch of the made-up layers, in site widths.
+    pub default_pitch: f64,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            mode: ParseMode::default(),
+            flavor: None,
+            default_layers: 4,
+            default_pitch: 1.0,
+        }
+    }
+}
+
+/// A reader running on tokio's blocking pool with its own diagnostics.
+type ReadTask<T> = JoinHandle<anyhow::Result<(T, Diagnostics)>>;
+
+fn spawn_read<T, F>(path: Option<PathBuf>, mode: ParseMode, read: F) -> Option<ReadTask<T>>
+where
+    T: Send + 'static,
+    F: FnOnce(&Path, &mut Diagnostics) -> anyhow::Result<T> + Send + 'static,
+{
+    let path = path?;
+    Some(tokio::task::spawn_blocking(move || {
+        let mut diagnostics = Diagnostics::new(mode);
+        let res = read(&path, &mut diagnostics)?;
+        Ok((res, diagnostics))
+    }))
+}
+
+async fn join_read<T>(task: ReadTask<T>, diagnostics: &mut Diagnostics) -> anyhow::Result<T> {
+    let (res, task_diagnostics) = task.await??;
+    diagnostics.append(task_diagnostics);
+    Ok(res)
 }
 
 impl Bookshelf {
-    pub async fn build_from_aux(aux: Aux) -> anyhow::Result<Self> {
+    /// The files are independent, so they are all parsed at once on the
+    /// blocking pool, then joined in a fixed order so that messages and
+    /// diagnostics do not depend on which file finishes first. Files inside
+    /// an archive are unpacked first, in one pass over it, rather than have
+    /// every reader decompress it again. Without a `.route`, routing layers
+    /// are made up as `options` says.
+    pub async fn build_from_aux(aux: Aux, options: &ReadOptions) -> anyhow::Result<Self> {
+        let mode = options.mode;
+        let paths: Vec<PathBuf> = [
+            &aux.nodes,
+            &aux.nets,
+            &aux.pl,
+            &aux.scl,
+            &aux.route,
+            &aux.shapes,
+            &aux.wts,
+        ]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect();
+        // removes the unpacked copies once everything is read
+        let unpacked =
+            tokio::task::spawn_blocking(move || Unpacked::new(paths.iter().map(PathBuf::as_path)))
+                .await??;
+        let path = |path: &Option<PathBuf>| Some(unpacked.get(path.as_deref()?).to_path_buf());
+        let nodes = spawn_read(path(&aux.nodes), mode, Nodes::read);
+        let nets = spawn_read(path(&aux.nets), mode, |path, diagnostics| {
+            Nets::read_from_file(path.to_path_buf(), diagnostics)
+        });
+        let pls = spawn_read(path(&aux.pl), mode, Pls::read_from_file);
+        let scl = spawn_read(path(&aux.scl), mode, Scl::read_from_file);
+        let route = spawn_read(path(&aux.route), mode, Route::read);
+        let shape = spawn_read(path(&aux.shapes), mode, Shapes::read_from_file);
+        let wts = spawn_read(path(&aux.wts), mode, Wts::read_from_file);
+
         let mut res = Self::default();
-        if let Some(nodes_path) = aux.nodes.as_ref() {
-            res.nodes = Nodes::read(nodes_path).await?;
+        let mut diagnostics = Diagnostics::new(mode);
+        if let Some(task) = nodes {
+            res.nodes = join_read(task, &mut diagnostics).await?;
             println!("Read {} nodes", res.nodes.len())
         };
-        if let Some(net_path) = aux.nets.as_ref() {
-            res.nets = Nets::read_from_file(net_path.clone()).await?;
+        if let Some(task) = nets {
+            res.nets = join_read(task, &mut diagnostics).await?;
             println!("Read {} nets", res.nets.len());
         }
 
-        if let Some(pl_path) = aux.pl.as_ref() {
-            res.pls = Pls::read_from_file(pl_path).await?;
+        if let Some(task) = pls {
+            res.pls = join_read(task, &mut diagnostics).await?;
             println!("Read {} pls", res.pls.len());
         }
 
-        if let Some(scl_path) = aux.scl.as_ref() {
-            res.scl = Scl::read_from_file(scl_path).await?;
+        if let Some(task) = scl {
+            res.scl = join_read(task, &mut diagnostics).await?;
             println!("Read {} rows", res.scl.len());
         }
 
-        if let Some(route_path) = aux.route.as_ref() {
-            res.route = Route::read(route_path).await?;
+        if let Some(task) = route {
+            res.route = join_read(task, &mut diagnostics).await?;
             println!(
                 "Read Route\
                 \n  {} Not in Image Terminals (akin metals on layer higher than M1. Like VSS in real design).\
@@ -44,14 +149,485 @@ impl Bookshelf {
             );
         }
 
-        if let Some(shape_path) = aux.shapes.as_ref() {
-            res.shape = Shapes::read_from_file(shape_path).await?;
+        if let Some(task) = shape {
+            res.shape = join_read(task, &mut diagnostics).await?;
             println!("Read {} shape.", res.shape.len());
         }
 
-        if aux.wts.as_ref().is_some() {
-            println!("Unimplemented wts!");
+        if let Some(task) = wts {
+            res.wts = join_read(task, &mut diagnostics).await?;
+            println!("Read {} weights", res.wts.len());
+            res.check_weight_names(&mut diagnostics);
+        }
+        res.check_references(&mut diagnostics);
+
+        let flavor = match options.flavor {
+            Some(flavor) => {
+                info!("Flavor set to {}", flavor);
+                flavor
+            }
+            None => {
+                let (flavor, reason) = Flavor::detect(&aux, &res.nodes);
+                info!("Detected {} benchmark ({})", flavor, reason);
+                flavor
+            }
+        };
+        res.set_flavor(flavor, &mut diagnostics)?;
+        res.diagnostics = diagnostics.into_vec();
+        res.report_diagnostics();
+        if res.route.is_empty() {
+            res.synthesize_route(options.default_layers, options.default_pitch)?;
         }
         Ok(res)
     }
+
+    /// Set the flavor and check that the data it comes with was read. A
+    /// routability benchmark without `.route` is an error, unless reading
+    /// leniently, which makes the routing layers up.
+    fn set_flavor(&mut self, flavor: Flavor, diagnostics: &mut Diagnostics) -> anyhow::Result<()> {
+        self.flavor = flavor;
+        if flavor.is_routability() && self.route.grid.num_layer == 0 {
+            let message = format!(
+                "{} benchmarks come with a .route file, but none was read",
+                flavor
+            );
+            match diagnostics.mode() {
+                ParseMode::Strict => {
+                    anyhow::bail!("{}; read leniently to make routing layers up", message)
+                }
+                ParseMode::Lenient => {
+                    diagnostics.warn(format!("{}; routing layers will be made up", message))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Make up the routing resources of a benchmark without `.route`: the die
+    /// covers the rows and every terminal, tiles are ten rows high, and
+    /// `num_layer` layers get a pitch of `pitch_in_sites` site widths.
+    /// `terminal_NI` pins go on metal2, above the cell pins.
+    pub fn synthesize_route(
+        &mut self,
+        num_layer: usize,
+        pitch_in_sites: f64,
+    ) -> anyhow::Result<()> {
+        let (Some(row), Some(core)) = (self.scl.iter().next(), self.scl.bounding_box()) else {
+            anyhow::bail!("cannot make up routing layers without rows in .scl");
+        };
+        if num_layer == 0 || pitch_in_sites <= 0.0 {
+            anyhow::bail!(
+                "cannot make up {} routing layers with a pitch of {} sites",
+                num_layer,
+                pitch_in_sites
+            );
+        }
+        let pitch = pitch_in_sites * row.site_step();
+        let tile_len = 10.0 * row.height;
+        let terminals = self
+            .nodes
+            .iter()
+            .filter(|x| !matches!(x.moveable, Movable::Movable))
+            .filter_map(|x| self.footprint(&x.name))
+            .flatten();
+        let die = terminals.fold(core, |res, x| res.union(&x));
+        let mut route = Route::synthesize(die, tile_len, num_layer, pitch);
+        let ni_layer = num_layer.min(2) as i64;
+        for node in self
+            .nodes
+            .iter()
+            .filter(|x| self.nodes.is_terminal_ni(&x.name))
+        {
+            route
+                .ni_terminal_to_layer
+                .insert(node.name.clone(), ni_layer);
+        }
+        warn!(
+            "No .route read; made up the routing resources:\
+            \n  * {} layers of pitch {} ({} site widths), metal1 without capacity\
+            \n  * tiles of {} by {} (about 10 rows) in a {}x{} grid over die {:?}\
+            \n  * terminal_NI pins on metal{}",
+            num_layer,
+            pitch,
+            pitch_in_sites,
+            route.tile_size.x,
+            route.tile_size.y,
+            route.grid.num_x,
+            route.grid.num_y,
+            die,
+            ni_layer
+        );
+        self.route = route;
+        Ok(())
+    }
+
+    /// Every weight should name a net or a node.
+    fn check_weight_names(&self, diagnostics: &mut Diagnostics) {
+        let net_names: HashSet<&str> = self.nets.iter().map(|x| x.name.as_str()).collect();
+        let unknown: Vec<&str> = self
+            .wts
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .filter(|name| !net_names.contains(name) && self.nodes.get(name).is_none())
+            .collect();
+        if !unknown.is_empty() {
+            diagnostics.warn(format!(
+                "{} weight(s) name neither a net nor a node, e.g. {}",
+                unknown.len(),
+                unknown[..unknown.len().min(5)].join(", ")
+            ));
+        }
+        let changed: Vec<String> = self
+            .wts
+            .changed_for_def()
+            .map(|(name, weight, def)| format!("{} ({} -> {})", name, weight, def))
+            .collect();
+        if !changed.is_empty() {
+            diagnostics.warn(format!(
+                "{} weight(s) are not positive integers, so DEF gets them rounded and at least 1, e.g. {}",
+                changed.len(),
+                changed[..changed.len().min(5)].join(", ")
+            ));
+        }
+    }
+
+    /// Drop what the writers cannot place, with a warning for each: nodes
+    /// without a `.pl` entry, `terminal_NI` pins without a `.route` layer,
+    /// `.route` terminals and blockages on nodes that are unknown or were
+    /// just dropped, then net pins on such nodes.
+    fn check_references(&mut self, diagnostics: &mut Diagnostics) {
+        let pls = &self.pls;
+        self.nodes.nodes.retain(|name, _| {
+            let placed = pls.get(name).is_some();
+            if !placed {
+                diagnostics.warn(format!("node {} has no .pl entry; dropped", name));
+            }
+            placed
+        });
+        // without `.route`, `synthesize_route` puts every pin on a layer
+        if !self.route.is_empty() {
+            let layers = &self.route.ni_terminal_to_layer;
+            self.nodes.nodes.retain(|name, node| {
+                let on_layer = !matches!(node.moveable, Movable::FixedButOverlapAllowed)
+                    || layers.contains_key(name);
+                if !on_layer {
+                    diagnostics.warn(format!(
+                        "terminal_NI {} has no NumNiTerminals entry in .route; dropped",
+                        name
+                    ));
+                }
+                on_layer
+            });
+        }
+        let nodes = &self.nodes;
+        self.route.ni_terminal_to_layer.retain(|name, _| {
+            let known = nodes.is_terminal_ni(name);
+            if !known {
+                diagnostics.warn(format!(
+                    "terminal {} in .route is no terminal_NI node with a .pl entry; dropped",
+                    name
+                ));
+            }
+            known
+        });
+        self.route.blockage_info.retain(|name, _| {
+            let known = nodes.get(name).is_some();
+            if !known {
+                diagnostics.warn(format!(
+                    ".route blocks layers over unknown node {}; dropped",
+                    name
+                ));
+            }
+            known
+        });
+        self.nets.retain_pins(|net_name, pin| {
+            let known = nodes.get(&pin.instance_name).is_some();
+            if !known {
+                diagnostics.warn(format!(
+                    "net {} has a pin on unknown node {}; dropped",
+                    net_name, pin.instance_name
+                ));
+            }
+            known
+        });
+    }
+
+    /// Center of `instance_name` plus `offset`, or `None` for an unknown or
+    /// unplaced node. Bookshelf pin offsets are relative to the node center
+    /// of the unflipped cell; the `.pl` orientation moves them.
+    pub fn pin_position(&self, instance_name: &str, offset: Point) -> Option<Point> {
+        let (center, orientation) = self.placed_center(instance_name)?;
+        let offset = orientation.apply(offset);
+        Some(Point {
+            x: center.x + offset.x,
+            y: center.y + offset.y,
+        })
+    }
+
+    /// Center of a placed node, and its orientation.
+    fn placed_center(&self, node_name: &str) -> Option<(Point, Orient)> {
+        let node = self.nodes.get(node_name)?;
+        let pl = self.pls.get(node_name)?;
+        let (width, height) = match pl.orientation.is_rotated() {
+            true => (node.size.y, node.size.x),
+            false => (node.size.x, node.size.y),
+        };
+        let center = Point {
+            x: pl.place.x + width / 2.0,
+            y: pl.place.y + height / 2.0,
+        };
+        Some((center, pl.orientation))
+    }
+
+    /// Where `node_name` sits on the chip: its `.shapes` rectangles, or its
+    /// placed bounding box. `None` for an unknown or unplaced node.
+    pub fn footprint(&self, node_name: &str) -> Option<Vec<Rect>> {
+        if let Some(node_shape) = self.shape.get(node_name) {
+            return Some(node_shape.rects().copied().collect());
+        }
+        let node = self.nodes.get(node_name)?;
+        let (center, orientation) = self.placed_center(node_name)?;
+        let half = match orientation.is_rotated() {
+            true => Point {
+                x: node.size.y / 2.0,
+                y: node.size.x / 2.0,
+            },
+            false => Point {
+                x: node.size.x / 2.0,
+                y: node.size.y / 2.0,
+            },
+        };
+        Some(vec![Rect {
+            ll: Point {
+                x: center.x - half.x,
+                y: center.y - half.y,
+            },
+            ur: Point {
+                x: center.x + half.x,
+                y: center.y + half.y,
+            },
+        }])
+    }
+
+    /// The `.shapes` rectangles of `node_name`, which are given as placed,
+    /// moved into its unflipped macro with the origin at the lower left.
+    /// `None` for a plain rectangular node.
+    pub fn macro_shape(&self, node_name: &str) -> Option<Vec<Rect>> {
+        let node_shape = self.shape.get(node_name)?;
+        let (center, orientation) = self.placed_center(node_name)?;
+        let size = self.nodes.get(node_name)?.size;
+        let inverse = orientation.inverse();
+        let rects = node_shape
+            .rects()
+            .map(|rect| {
+                rect.map_corners(|p| {
+                    let local = inverse.apply(Point {
+                        x: p.x - center.x,
+                        y: p.y - center.y,
+                    });
+                    Point {
+                        x: local.x + size.x / 2.0,
+                        y: local.y + size.y / 2.0,
+                    }
+                })
+            })
+            .collect();
+        Some(rects)
+    }
+
+    /// Half-perimeter wirelength, each net scaled by its `.wts` weight
+    /// (1 when it has none). Pins on unknown nodes are ignored.
+    pub fn hpwl(&self) -> f64 {
+        self.nets
+            .iter()
+            .map(|net| {
+                let mut pins = net.pin.iter().filter_map(|pin| {
+                    self.pin_position(&pin.instance_name, pin.offset.unwrap_or_default())
+                });
+                let Some(first) = pins.next() else {
+                    return 0.0;
+                };
+                let (ll, ur) = pins.fold((first, first), |(ll, ur), p| {
+                    (
+                        Point {
+                            x: ll.x.min(p.x),
+                            y: ll.y.min(p.y),
+                        },
+                        Point {
+                            x: ur.x.max(p.x),
+                            y: ur.y.max(p.y),
+                        },
+                    )
+                });
+                let weight = self.wts.get(&net.name).unwrap_or(1.0);
+                weight * ((ur.x - ll.x) + (ur.y - ll.y))
+            })
+            .sum()
+    }
+
+    pub fn report_diagnostics(&self) {
+        if self.diagnostics.is_empty() {
+            return;
+        }
+        for diagnostic in self.diagnostics.iter() {
+            warn!("{}", diagnostic);
+        }
+        let num_errors = self
+            .diagnostics
+            .iter()
+            .filter(|x| x.severity == Severity::Error)
+            .count();
+        let num_warnings = self.diagnostics.len() - num_errors;
+        if num_errors > 0 {
+            warn!("Skipped {} malformed record(s), see above.", num_errors);
+        }
+        if num_warnings > 0 {
+            warn!(
+                "{} warning(s), see above. The input may be truncated or inconsistent.",
+                num_warnings
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        io::test_util::{read_bookshelf, read_fixture},
+        lefdef::pin::DefPins,
+    };
+
+    #[test]
+    fn unplaced_nodes_and_their_pins_are_dropped() {
+        let (nodes, _) = read_fixture(
+            "check.nodes",
+            b"UCLA nodes 1.0\na 1 1\nb 1 1\np 0 0 terminal_NI\n",
+            ParseMode::Strict,
+            Nodes::read,
+        );
+        let (nets, _) = read_fixture(
+            "check.nets",
+            b"UCLA nets 1.0\nNetDegree : 3 n\na I\nb O\nghost I\n",
+            ParseMode::Strict,
+            |path, diagnostics| Nets::read_from_file(path.to_path_buf(), diagnostics),
+        );
+        let (pls, _) = read_fixture(
+            "check.pl",
+            b"UCLA pl 1.0\na 0 0 : N\np 5 5 : N /FIXED_NI\n",
+            ParseMode::Strict,
+            Pls::read_from_file,
+        );
+        let mut bookshelf = Bookshelf {
+            nodes: nodes.unwrap(),
+            nets: nets.unwrap(),
+            pls: pls.unwrap(),
+            ..Default::default()
+        };
+        let mut diagnostics = Diagnostics::new(ParseMode::Lenient);
+        bookshelf.check_references(&mut diagnostics);
+        let names: Vec<&str> = bookshelf.nodes.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, ["a", "p"]);
+        let net = bookshelf.nets.iter().next().unwrap();
+        assert_eq!(net.pin.len(), 1);
+        assert_eq!(net.pin[0].instance_name, "a");
+        let messages: Vec<String> = diagnostics.iter().map(|x| x.to_string()).collect();
+        assert_eq!(
+            messages,
+            [
+                "node b has no .pl entry; dropped",
+                "net n has a pin on unknown node b; dropped",
+                "net n has a pin on unknown node ghost; dropped",
+            ]
+        );
+        assert!(bookshelf.nodes.is_terminal_ni("p"));
+        assert!(!bookshelf.nodes.is_terminal_ni("ghost"));
+    }
+
+    #[test]
+    fn terminal_ni_pins_need_a_pl_entry_and_a_layer() {
+        let (nodes, _) = read_fixture(
+            "ni.nodes",
+            b"UCLA nodes 1.0\na 1 1\np 0 0 terminal_NI\nq 0 0 terminal_NI\nr 0 0 terminal_NI\n",
+            ParseMode::Lenient,
+            Nodes::read,
+        );
+        let (nets, _) = read_fixture(
+            "ni.nets",
+            b"UCLA nets 1.0\nNetDegree : 4 n\na I\np O\nq O\nr O\n",
+            ParseMode::Lenient,
+            |path, diagnostics| Nets::read_from_file(path.to_path_buf(), diagnostics),
+        );
+        let (pls, _) = read_fixture(
+            "ni.pl",
+            b"UCLA pl 1.0\na 0 0 : N\np 5 5 : N /FIXED_NI\nr 6 6 : N /FIXED_NI\n",
+            ParseMode::Lenient,
+            Pls::read_from_file,
+        );
+        let (route, _) = read_fixture(
+            "ni.route",
+            b"route 1.0\nGrid : 2 2 1\nMinWireWidth : 1\n\
+            NumNiTerminals : 3\np 1\nq 1\nghost 1\nNumBlockageNodes : 1\nq 1 1\n",
+            ParseMode::Lenient,
+            Route::read,
+        );
+        let mut bookshelf = Bookshelf {
+            nodes: nodes.unwrap(),
+            nets: nets.unwrap(),
+            pls: pls.unwrap(),
+            route: route.unwrap(),
+            ..Default::default()
+        };
+        let mut diagnostics = Diagnostics::new(ParseMode::Lenient);
+        bookshelf.check_references(&mut diagnostics);
+        let messages: Vec<String> = diagnostics.iter().map(|x| x.to_string()).collect();
+        assert_eq!(
+            messages,
+            [
+                "node q has no .pl entry; dropped",
+                "terminal_NI r has no NumNiTerminals entry in .route; dropped",
+                "terminal ghost in .route is no terminal_NI node with a .pl entry; dropped",
+                "terminal q in .route is no terminal_NI node with a .pl entry; dropped",
+                ".route blocks layers over unknown node q; dropped",
+                "net n has a pin on unknown node q; dropped",
+                "net n has a pin on unknown node r; dropped",
+            ]
+        );
+        let pins = DefPins::build(&bookshelf).unwrap();
+        assert!(pins.is_defpin("p"));
+        assert!(!pins.is_defpin("q") && !pins.is_defpin("r"));
+    }
+
+    #[test]
+    fn made_up_routes_cover_the_rows_and_terminals() {
+        let mut bookshelf = read_bookshelf(
+            "synthesized",
+            &[
+                (
+                    "scl",
+                    b"CoreRow Horizontal\n Coordinate : 0\n Height : 2\n Sitewidth : 1\n\
+                    SubrowOrigin : 0 NumSites : 100\nEnd\n",
+                ),
+                ("nodes", b"a 1 2\nt 4 4 terminal\np 1 1 terminal_NI\n"),
+                (
+                    "pl",
+                    b"a 0 0 : N\nt 110 30 : N /FIXED\np 50 20 : N /FIXED_NI\n",
+                ),
+            ],
+        );
+        bookshelf.synthesize_route(3, 2.0).unwrap();
+        let route = &bookshelf.route;
+        // the die is 114 by 34, cut into tiles of about 10 rows of 2
+        assert_eq!((route.grid.num_x, route.grid.num_y), (6, 2));
+        assert_eq!((route.tile_size.x, route.tile_size.y), (19.0, 17.0));
+        assert_eq!(route.vertical_capacity, [0, 18, 0]);
+        assert_eq!(route.horizontal_capacity, [0, 0, 16]);
+        assert_eq!(route.min_wire_width, [1.0, 1.0, 1.0]);
+        assert_eq!(route.ni_terminal_to_layer["p"], 2);
+        bookshelf.synthesize_route(1, 2.0).unwrap();
+        assert_eq!(bookshelf.route.ni_terminal_to_layer["p"], 1);
+        assert!(bookshelf.synthesize_route(0, 2.0).is_err());
+        assert!(bookshelf.synthesize_route(3, 0.0).is_err());
+        assert!(Bookshelf::default().synthesize_route(3, 2.0).is_err());
+    }
 }
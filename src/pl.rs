@@ -1,10 +1,14 @@
-use crate::{geom::Point, io::reader::TokenReader};
-use std::{collections::BTreeMap, fs::File, io::BufReader, path::PathBuf};
+use crate::{
+    diagnostics::Diagnostics,
+    geom::{Orient, Point},
+    io::reader::{CommonReader, Keyword},
+};
+use std::{collections::BTreeMap, path::Path};
 
 pub struct Pl {
     pub name: String,
     pub place: Point,
-    pub orientation: String,
+    pub orientation: Orient,
     pub r#type: Type,
 }
 
@@ -16,23 +20,22 @@ pub enum Type {
     FixedNotInImage,
 }
 impl Pl {
-    pub async fn read(reader: &mut TokenReader<BufReader<File>>) -> anyhow::Result<Self> {
-        let name = reader.next_token()?.unwrap().to_string();
-        let place = Point::read(reader).await?;
-        assert_eq!(":", reader.next_token()?.unwrap());
-        let orientation = reader.next_token()?.unwrap().to_string();
-        let r#type = if let Some(next_token) = reader.peek_token()? {
-            if next_token.to_ascii_uppercase().as_bytes() == b"/FIXED" {
+    pub fn read(reader: &mut CommonReader) -> anyhow::Result<Self> {
+        let name = reader.next_string("node name")?;
+        let place = Point::read(reader)?;
+        reader.expect(":")?;
+        let orientation = reader.next_parse("orientation (N, S, E, W, FN, FS, FE or FW)")?;
+        let keyword = reader.peek_token()?.map(Keyword::new);
+        let r#type = match keyword.as_ref().map(Keyword::as_bytes) {
+            Some(b"/FIXED") => {
                 reader.next_token()?;
                 Type::Fixed
-            } else if next_token.to_ascii_uppercase().as_bytes() == b"/FIXED_NI" {
+            }
+            Some(b"/FIXED_NI") => {
                 reader.next_token()?;
                 Type::FixedNotInImage
-            } else {
-                Type::Movable
             }
-        } else {
-            Type::Movable
+            _ => Type::Movable,
         };
         Ok(Pl {
             name,
@@ -56,23 +59,66 @@ impl Pls {
         self.pls.len()
     }
 
-    pub  fn get(&self, name: &str) -> Option<&Pl> {
+    pub fn is_empty(&self) -> bool {
+        self.pls.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Pl> {
         self.pls.get(name)
     }
-    pub async fn read_from_file(file_path: &PathBuf) -> anyhow::Result<Self> {
+    pub fn read_from_file(
+        file_path: &Path,
+        diagnostics: &mut Diagnostics,
+    ) -> anyhow::Result<Self> {
         let mut res = Self::default();
-        let mut reader = crate::io::reader::TokenReader::new_from_path(file_path);
-        while let Some(token) = reader.peek_token()? {
-            match token.to_ascii_uppercase().as_bytes() {
-                b"UCLA" | b"#" => {
-                    reader.swallow_line()?;
-                }
-                _ => {
-                    let place = Pl::read(&mut reader).await?;
+        let mut reader = CommonReader::new_from_path(file_path)?;
+        while reader.peek_token()?.is_some() {
+            match reader.read_line(Pl::read) {
+                Ok(place) => {
                     res.pls.insert(place.name.clone(), place);
                 }
+                Err(e) => {
+                    diagnostics.recover(e)?;
+                    reader.swallow_line()?;
+                }
             }
         }
         Ok(res)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{diagnostics::ParseMode, io::test_util::read_fixture};
+
+    const TRUNCATED: &[u8] = b"UCLA pl 1.0\na 1 2 :\nb 3 4 : FS /FIXED\nc 5\nd 6 7 : N\n";
+
+    #[test]
+    fn lenient_skips_only_the_truncated_records() {
+        let (pls, messages) = read_fixture(
+            "truncated.pl",
+            TRUNCATED,
+            ParseMode::Lenient,
+            Pls::read_from_file,
+        );
+        let pls = pls.unwrap();
+        let names: Vec<&str> = pls.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, ["b", "d"]);
+        assert!(matches!(pls.get("b").unwrap().r#type, Type::Fixed));
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].contains(":2:7: expected orientation"));
+        assert!(messages[1].contains(":4:3: expected y coordinate, found end of line"));
+    }
+
+    #[test]
+    fn strict_stops_at_the_first_truncated_record() {
+        let (pls, _) = read_fixture(
+            "strict.pl",
+            TRUNCATED,
+            ParseMode::Strict,
+            Pls::read_from_file,
+        );
+        assert!(pls.is_err());
+    }
+}
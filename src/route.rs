@@ -1,6 +1,10 @@
-use std::{collections::BTreeMap, path::PathBuf};
+use std::{collections::BTreeMap, path::Path};
 
-use crate::{geom::Point, io::reader::CommonReader, util};
+use crate::{
+    diagnostics::Diagnostics,
+    geom::{Point, Rect},
+    io::reader::{CommonReader, FromToken, Keyword},
+};
 
 #[derive(Default)]
 pub struct Grid {
@@ -10,14 +14,17 @@ pub struct Grid {
 }
 
 impl Grid {
-    pub async fn read(reader: &mut CommonReader) -> anyhow::Result<Self> {
-        let mut res = Grid::default();
-        assert_eq!("Grid", reader.next_token()?.unwrap());
-        assert_eq!(":", reader.next_token()?.unwrap());
-        res.num_x = str::parse::<i64>(reader.next_token()?.unwrap())?;
-        res.num_y = str::parse::<i64>(reader.next_token()?.unwrap())?;
-        res.num_layer = str::parse::<i64>(reader.next_token()?.unwrap())?;
-        Ok(res)
+    pub fn read(reader: &mut CommonReader) -> anyhow::Result<Self> {
+        reader.expect("Grid")?;
+        reader.expect(":")?;
+        let num_x = reader.next_parse::<i64>("number of grid columns")?;
+        let num_y = reader.next_parse::<i64>("number of grid rows")?;
+        let num_layer = reader.next_parse::<i64>("number of layers")?;
+        Ok(Self {
+            num_x,
+            num_y,
+            num_layer,
+        })
     }
 }
 
@@ -25,45 +32,99 @@ type TerminalLayer = BTreeMap<String, i64>;
 pub trait TerminalLayerReader {
     fn read(
         reader: &mut CommonReader,
-    ) -> impl std::future::Future<Output = anyhow::Result<TerminalLayer>> + Send;
+        diagnostics: &mut Diagnostics,
+    ) -> anyhow::Result<TerminalLayer>;
+}
+
+fn read_terminal_layer(reader: &mut CommonReader) -> anyhow::Result<(String, i64)> {
+    let name = reader.next_string("terminal name")?;
+    let layer_id = reader.read_line(|reader| reader.next_parse("terminal layer id"))?;
+    Ok((name, layer_id))
 }
+
 impl TerminalLayerReader for TerminalLayer {
-    async fn read(reader: &mut CommonReader) -> anyhow::Result<TerminalLayer> {
+    fn read(
+        reader: &mut CommonReader,
+        diagnostics: &mut Diagnostics,
+    ) -> anyhow::Result<TerminalLayer> {
         let mut res = TerminalLayer::default();
-        reader.next_token()?;
-        assert_eq!(":", reader.next_token()?.unwrap());
-        let num_terminal: i64 = util::parse(reader.next_token()?.unwrap());
+        reader.expect("NumNiTerminals")?;
+        reader.expect(":")?;
+        let num_terminal = reader.next_parse::<i64>("number of NI terminals")?;
         for _ in 0..num_terminal {
-            let name = reader.next_token()?.unwrap().to_string();
-            let layer_id = util::parse(reader.next_token()?.unwrap());
-            res.insert(name, layer_id);
+            match read_terminal_layer(reader) {
+                Ok((name, layer_id)) => {
+                    res.insert(name, layer_id);
+                }
+                Err(e) => {
+                    diagnostics.recover(e)?;
+                    reader.swallow_line()?;
+                }
+            }
         }
         Ok(res)
     }
 }
 
+/// `<keyword> : <value per layer>`, all on the keyword's line. Values read
+/// before a malformed one are kept.
+fn read_layer_values<T: FromToken>(
+    reader: &mut CommonReader,
+    what: &str,
+    num_layer: i64,
+    values: &mut Vec<T>,
+) -> anyhow::Result<()> {
+    reader.next_token()?;
+    reader.read_line(|reader| {
+        reader.expect(":")?;
+        for _ in 0..num_layer {
+            values.push(reader.next_parse(what)?);
+        }
+        Ok(())
+    })
+}
+
 type BlockageInfo = BTreeMap<String, Vec<i64>>;
 pub trait BlockageInfoReader {
     fn read(
         reader: &mut CommonReader,
-    ) -> impl std::future::Future<Output = anyhow::Result<BlockageInfo>> + Send;
+        diagnostics: &mut Diagnostics,
+    ) -> anyhow::Result<BlockageInfo>;
+}
+
+fn read_blockage_node(reader: &mut CommonReader) -> anyhow::Result<(String, Vec<i64>)> {
+    let terminal_name = reader.next_string("blockage node name")?;
+    let blockage_on_layer_ids = reader.read_line(|reader| {
+        let mut blockage_on_layer_ids = vec![];
+        let num_layer = reader.next_parse::<i64>("number of blocked layers")?;
+        for _ in 0..num_layer {
+            let layer_id = reader.next_parse::<i64>("blocked layer id")?;
+            blockage_on_layer_ids.push(layer_id);
+        }
+        Ok(blockage_on_layer_ids)
+    })?;
+    Ok((terminal_name, blockage_on_layer_ids))
 }
 
 impl BlockageInfoReader for BlockageInfo {
-    async fn read(reader: &mut CommonReader) -> anyhow::Result<BlockageInfo> {
+    fn read(
+        reader: &mut CommonReader,
+        diagnostics: &mut Diagnostics,
+    ) -> anyhow::Result<BlockageInfo> {
         let mut res = BlockageInfo::new();
-        assert_eq!("NumBlockageNodes", reader.next_token()?.unwrap());
-        assert_eq!(":", reader.next_token()?.unwrap());
-        let num_blockage: i64 = crate::util::parse(reader.next_token()?.unwrap());
+        reader.expect("NumBlockageNodes")?;
+        reader.expect(":")?;
+        let num_blockage = reader.next_parse::<i64>("number of blockage nodes")?;
         for _ in 0..num_blockage {
-            let terminal_name = reader.next_token()?.unwrap().to_string();
-            let mut blockage_on_layer_ids = vec![];
-            let num_layer: i64 = crate::util::parse(reader.next_token()?.unwrap());
-            for _ in 0..num_layer {
-                let layer_id: i64 = crate::util::parse(reader.next_token()?.unwrap());
-                blockage_on_layer_ids.push(layer_id);
-            }
-            res.insert(terminal_name, blockage_on_layer_ids);
+            match read_blockage_node(reader) {
+                Ok((terminal_name, blockage_on_layer_ids)) => {
+                    res.insert(terminal_name, blockage_on_layer_ids);
+                }
+                Err(e) => {
+                    diagnostics.recover(e)?;
+                    reader.swallow_line()?;
+                }
+            }
         }
         Ok(res)
     }
@@ -74,12 +135,12 @@ pub struct Route {
     pub grid: Grid,
     pub vertical_capacity: Vec<i64>,
     pub horizontal_capacity: Vec<i64>,
-    pub min_wire_width: Vec<i64>,
-    pub min_wire_spacing: Vec<i64>,
-    pub via_spacing: Vec<i64>,
+    pub min_wire_width: Vec<f64>,
+    pub min_wire_spacing: Vec<f64>,
+    pub via_spacing: Vec<f64>,
     pub grid_origin: Point,
     pub tile_size: Point,
-    pub blockage_porosity: i64,
+    pub blockage_porosity: f64,
     pub ni_terminal_to_layer: TerminalLayer, // not in image terminal (fixed pin above M1)
     pub blockage_info: BlockageInfo,
 }
@@ -91,83 +152,297 @@ impl Route {
     pub fn ni_terminal_len(&self) -> usize {
         self.ni_terminal_to_layer.len()
     }
-    pub async fn read(route_path: &PathBuf) -> anyhow::Result<Self> {
-        let reader = &mut CommonReader::new_from_path(route_path);
+
+    /// Share of routing capacity left on blocked layers over a blocked node,
+    /// in `[0, 1]`. `BlockagePorosity` is a fraction, 0 in every ISPD 2011
+    /// benchmark; values above 1 are taken as percent and the rest is
+    /// clamped, see [`Self::check_porosity`].
+    pub fn porosity(&self) -> f64 {
+        let porosity = if self.blockage_porosity > 1.0 {
+            self.blockage_porosity / 100.0
+        } else {
+            self.blockage_porosity
+        };
+        porosity.clamp(0.0, 1.0)
+    }
+
+    /// Whether no routing resources were read, as without a `.route` file.
+    pub fn is_empty(&self) -> bool {
+        self.grid.num_layer == 0 && self.min_wire_width.is_empty()
+    }
+
+    /// Routing resources for a benchmark without `.route`: `die` cut into
+    /// tiles of about `tile_len`, and `num_layer` layers of one `pitch`, half
+    /// wire and half spacing. As in ISPD 2011, metal1 has no capacity and
+    /// the layers above alternate vertical and horizontal; capacities are
+    /// the tracks fitting in a tile times their pitch.
+    pub fn synthesize(die: Rect, tile_len: f64, num_layer: usize, pitch: f64) -> Self {
+        let num_x = (die.width() / tile_len).round().max(1.0);
+        let num_y = (die.height() / tile_len).round().max(1.0);
+        let tile_size = Point {
+            x: die.width() / num_x,
+            y: die.height() / num_y,
+        };
+        let capacity = |tile_len: f64| ((tile_len / pitch).floor() * pitch).round() as i64;
+        let mut res = Self {
+            grid: Grid {
+                num_x: num_x as i64,
+                num_y: num_y as i64,
+                num_layer: num_layer as i64,
+            },
+            grid_origin: die.ll,
+            tile_size,
+            ..Default::default()
+        };
+        for layer_id in 1..=num_layer {
+            let (vertical, horizontal) = match layer_id {
+                1 => (0, 0),
+                x if x % 2 == 0 => (capacity(tile_size.x), 0),
+                _ => (0, capacity(tile_size.y)),
+            };
+            res.vertical_capacity.push(vertical);
+            res.horizontal_capacity.push(horizontal);
+            res.min_wire_width.push(pitch / 2.0);
+            res.min_wire_spacing.push(pitch / 2.0);
+            res.via_spacing.push(0.0);
+        }
+        res
+    }
+    pub fn read(route_path: &Path, diagnostics: &mut Diagnostics) -> anyhow::Result<Self> {
+        let reader = &mut CommonReader::new_from_path(route_path)?;
         let mut res = Self::default();
         while let Some(token) = reader.peek_token()? {
-            match token.to_ascii_uppercase().as_bytes() {
-                b"ROUTE" | b"#" => {
-                    reader.swallow_line()?;
-                }
-                b"GRID" => {
-                    res.grid = Grid::read(reader).await?;
-                }
-                b"VERTICALCAPACITY" => {
-                    reader.next_token()?;
-                    assert_eq!(":", reader.next_token()?.unwrap());
-                    for _ in 0..res.grid.num_layer {
-                        res.vertical_capacity
-                            .push(crate::util::parse(reader.next_token()?.unwrap()));
-                    }
-                }
-                b"HORIZONTALCAPACITY" => {
-                    reader.next_token()?;
-                    assert_eq!(":", reader.next_token()?.unwrap());
-                    for _ in 0..res.grid.num_layer {
-                        res.horizontal_capacity
-                            .push(crate::util::parse(reader.next_token()?.unwrap()));
-                    }
-                }
-                b"MINWIREWIDTH" => {
-                    reader.next_token()?;
-                    assert_eq!(":", reader.next_token()?.unwrap());
-                    for _ in 0..res.grid.num_layer {
-                        res.min_wire_width
-                            .push(crate::util::parse(reader.next_token()?.unwrap()));
-                    }
-                }
-                b"MINWIRESPACING" => {
-                    reader.next_token()?;
-                    assert_eq!(":", reader.next_token()?.unwrap());
-                    for _ in 0..res.grid.num_layer {
-                        res.min_wire_spacing
-                            .push(crate::util::parse(reader.next_token()?.unwrap()));
-                    }
-                }
-                b"VIASPACING" => {
-                    reader.next_token()?;
-                    assert_eq!(":", reader.next_token()?.unwrap());
-                    for _ in 0..res.grid.num_layer {
-                        res.via_spacing
-                            .push(crate::util::parse(reader.next_token()?.unwrap()));
-                    }
-                }
-                b"GRIDORIGIN" => {
-                    reader.next_token()?;
-                    assert_eq!(":", reader.next_token()?.unwrap());
-                    res.grid_origin = Point::read(reader).await?;
-                }
-                b"TILESIZE" => {
-                    reader.next_token()?;
-                    assert_eq!(":", reader.next_token()?.unwrap());
-                    res.tile_size = Point::read(reader).await?;
-                }
-                b"BLOCKAGEPOROSITY" => {
-                    reader.next_token()?;
-                    assert_eq!(":", reader.next_token()?.unwrap());
-                    res.blockage_porosity = crate::util::parse(reader.next_token()?.unwrap());
-                }
-                b"NUMNITERMINALS" => {
-                    res.ni_terminal_to_layer = TerminalLayer::read(reader).await?;
-                }
-                b"NUMBLOCKAGENODES" => {
-                    res.blockage_info = BlockageInfo::read(reader).await?;
-                }
-                _ => {
-                    panic!("Unknown token: {}", token);
-                }
+            let keyword = Keyword::new(token);
+            if let Err(e) = res.read_section(reader, &keyword, diagnostics) {
+                diagnostics.recover(e)?;
+                reader.swallow_line()?;
             }
         }
+        res.check_layers(route_path, diagnostics);
+        res.check_porosity(route_path, diagnostics);
         Ok(res)
     }
+
+    /// Warn about a `BlockagePorosity` [`Self::porosity`] has to guess the
+    /// unit of: 1 reads as fully open, not 1%, values up to 100 as percent,
+    /// and anything else is clamped.
+    fn check_porosity(&self, route_path: &Path, diagnostics: &mut Diagnostics) {
+        let value = self.blockage_porosity;
+        let how = match value {
+            x if (0.0..1.0).contains(&x) => return,
+            1.0 => "a fraction, i.e. fully open, not 1%",
+            x if x > 1.0 && x <= 100.0 => "percent",
+            _ => "out of range and clamped",
+        };
+        diagnostics.warn(format!(
+            "{}: BlockagePorosity {} is read as {}, leaving {}% of the capacity",
+            route_path.display(),
+            value,
+            how,
+            self.porosity() * 100.0
+        ));
+    }
+
+    /// Bring every per-layer vector to `num_layer` values, as a skipped or
+    /// repeated section leaves them shorter or longer. Missing capacities and
+    /// via spacings are 0, missing wire widths and spacings copy the layer
+    /// below, or are 0.5 when none was read.
+    fn check_layers(&mut self, route_path: &Path, diagnostics: &mut Diagnostics) {
+        let num_layer = self.grid.num_layer.max(0) as usize;
+        let read = [
+            ("VerticalCapacity", self.vertical_capacity.len()),
+            ("HorizontalCapacity", self.horizontal_capacity.len()),
+            ("MinWireWidth", self.min_wire_width.len()),
+            ("MinWireSpacing", self.min_wire_spacing.len()),
+            ("ViaSpacing", self.via_spacing.len()),
+        ];
+        for (what, len) in read {
+            if len != num_layer {
+                diagnostics.warn(format!(
+                    "{}: {} has {} value(s) for {} layers; {}",
+                    route_path.display(),
+                    what,
+                    len,
+                    num_layer,
+                    match len > num_layer {
+                        true => "dropped the extra ones",
+                        false => "made up the missing ones",
+                    }
+                ));
+            }
+        }
+        let below_or = |values: &Vec<f64>| values.last().copied().unwrap_or(0.5);
+        self.vertical_capacity.resize(num_layer, 0);
+        self.horizontal_capacity.resize(num_layer, 0);
+        self.min_wire_width
+            .resize(num_layer, below_or(&self.min_wire_width));
+        self.min_wire_spacing
+            .resize(num_layer, below_or(&self.min_wire_spacing));
+        self.via_spacing.resize(num_layer, 0.0);
+    }
+
+    fn read_section(
+        &mut self,
+        reader: &mut CommonReader,
+        keyword: &Keyword,
+        diagnostics: &mut Diagnostics,
+    ) -> anyhow::Result<()> {
+        match keyword.as_bytes() {
+            b"ROUTE" => {
+                reader.swallow_line()?;
+            }
+            b"GRID" => {
+                self.grid = Grid::read(reader)?;
+            }
+            b"VERTICALCAPACITY" => {
+                let num_layer = self.grid.num_layer;
+                read_layer_values(
+                    reader,
+                    "vertical capacity",
+                    num_layer,
+                    &mut self.vertical_capacity,
+                )?;
+            }
+            b"HORIZONTALCAPACITY" => {
+                let num_layer = self.grid.num_layer;
+                read_layer_values(
+                    reader,
+                    "horizontal capacity",
+                    num_layer,
+                    &mut self.horizontal_capacity,
+                )?;
+            }
+            b"MINWIREWIDTH" => {
+                let num_layer = self.grid.num_layer;
+                read_layer_values(
+                    reader,
+                    "min wire width",
+                    num_layer,
+                    &mut self.min_wire_width,
+                )?;
+            }
+            b"MINWIRESPACING" => {
+                let num_layer = self.grid.num_layer;
+                read_layer_values(
+                    reader,
+                    "min wire spacing",
+                    num_layer,
+                    &mut self.min_wire_spacing,
+                )?;
+            }
+            b"VIASPACING" => {
+                let num_layer = self.grid.num_layer;
+                read_layer_values(reader, "via spacing", num_layer, &mut self.via_spacing)?;
+            }
+            b"GRIDORIGIN" => {
+                reader.next_token()?;
+                reader.expect(":")?;
+                self.grid_origin = Point::read(reader)?;
+            }
+            b"TILESIZE" => {
+                reader.next_token()?;
+                reader.expect(":")?;
+                self.tile_size = Point::read(reader)?;
+            }
+            b"BLOCKAGEPOROSITY" => {
+                reader.next_token()?;
+                reader.expect(":")?;
+                self.blockage_porosity = reader.next_parse("blockage porosity")?;
+            }
+            b"NUMNITERMINALS" => {
+                self.ni_terminal_to_layer = TerminalLayer::read(reader, diagnostics)?;
+            }
+            b"NUMBLOCKAGENODES" => {
+                self.blockage_info = BlockageInfo::read(reader, diagnostics)?;
+            }
+            _ => {
+                let found = reader.next_bytes("route keyword")?.to_vec();
+                return Err(reader.error("route keyword", Some(&found)).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{diagnostics::ParseMode, io::test_util::read_fixture};
+
+    #[test]
+    fn short_layer_vectors_are_padded() {
+        let (route, messages) = read_fixture(
+            "short.route",
+            b"route 1.0\nGrid : 2 2 3\nVerticalCapacity : 0 20 0\n\
+            HorizontalCapacity : 0 0\nMinWireWidth : 1 2 x\nMinWireSpacing : 1 1 1\n\
+            ViaSpacing : 0 0 0 0\n",
+            ParseMode::Lenient,
+            Route::read,
+        );
+        let route = route.unwrap();
+        assert_eq!(route.vertical_capacity, [0, 20, 0]);
+        assert_eq!(route.horizontal_capacity, [0, 0, 0]);
+        assert_eq!(route.min_wire_width, [1.0, 2.0, 2.0]);
+        assert_eq!(route.via_spacing, [0.0, 0.0, 0.0]);
+        assert!(
+            messages
+                .iter()
+                .any(|x| x.contains("HorizontalCapacity has 2 value(s)"))
+        );
+        assert!(
+            messages
+                .iter()
+                .any(|x| x.contains("MinWireWidth has 2 value(s)"))
+        );
+    }
+
+    #[test]
+    fn porosity_units() {
+        let read = |porosity: &str| {
+            let (route, messages) = read_fixture(
+                &format!("porosity{}.route", porosity),
+                format!("route 1.0\nBlockagePorosity : {}\n", porosity).as_bytes(),
+                ParseMode::Strict,
+                Route::read,
+            );
+            (route.unwrap().porosity(), messages.len())
+        };
+        assert_eq!(read("0"), (0.0, 0));
+        assert_eq!(read("0.25"), (0.25, 0));
+        assert_eq!(read("1"), (1.0, 1));
+        assert_eq!(read("50"), (0.5, 1));
+        assert_eq!(read("150"), (1.0, 1));
+        assert_eq!(read("-1"), (0.0, 1));
+    }
+
+    #[test]
+    fn truncated_terminals_and_blockages_keep_the_next_line() {
+        let (route, messages) = read_fixture(
+            "truncated.route",
+            b"route 1.0\nNumNiTerminals : 2\np\nq 2\n\
+            NumBlockageNodes : 2\na 2 1\nb 1 3\n",
+            ParseMode::Lenient,
+            Route::read,
+        );
+        let route = route.unwrap();
+        let terminals: Vec<(&str, i64)> = route
+            .ni_terminal_to_layer
+            .iter()
+            .map(|(name, layer_id)| (name.as_str(), *layer_id))
+            .collect();
+        assert_eq!(terminals, [("q", 2)]);
+        let blocked: Vec<&str> = route.blockage_info.keys().map(|x| x.as_str()).collect();
+        assert_eq!(blocked, ["b"]);
+        assert_eq!(route.blockage_info["b"], [3]);
+        assert!(
+            messages
+                .iter()
+                .any(|x| x.ends_with(":3:1: expected terminal layer id, found end of line"))
+        );
+        assert!(
+            messages
+                .iter()
+                .any(|x| x.ends_with(":6:5: expected blocked layer id, found end of line"))
+        );
+    }
 }
@@ -1,6 +1,10 @@
-use std::path::PathBuf;
+use std::path::Path;
 
-use crate::io::reader::CommonReader;
+use crate::{
+    diagnostics::Diagnostics,
+    geom::{Orient, Point, Rect, Symmetry},
+    io::reader::{CommonReader, FromToken, Keyword},
+};
 
 #[derive(Default, Clone, Debug)]
 pub enum RowOrientation {
@@ -9,57 +13,134 @@ pub enum RowOrientation {
     Vertical,
 }
 
-#[derive(Default, Clone, Debug)]
-pub enum SiteOrient {
-    #[default]
-    N,
-    FS,
+/// `Siteorient` and `Sitesymmetry` are plain numbers in the ISPD 2005/2006
+/// files, where they carry no meaning; they read as no orientation and `Y`.
+fn is_legacy_number(token: &[u8]) -> bool {
+    i64::from_token(token).is_some()
 }
 
-impl From<&str> for SiteOrient {
-    fn from(value: &str) -> Self {
-        match value.to_ascii_uppercase().as_bytes() {
-            b"N" => Self::N,
-            b"FS" => Self::FS,
-            _ => panic!("found {} only support N or S in SiteOrient", value),
+fn read_site_orient(reader: &mut CommonReader) -> anyhow::Result<Option<Orient>> {
+    let token = reader.next_bytes("site orientation")?;
+    if is_legacy_number(token) {
+        return Ok(None);
+    }
+    match Orient::from_token(token) {
+        Some(x) => Ok(Some(x)),
+        None => {
+            let found = token.to_vec();
+            Err(reader
+                .error("site orientation (N, S, E, W, FN, FS, FE or FW)", Some(&found))
+                .into())
         }
     }
 }
 
-#[derive(Default, Clone,Debug)]
-pub enum SiteSymmetry {
-    X,
-    #[default]
-    Y,
-}
-
-impl From<&str> for SiteSymmetry {
-    fn from(value: &str) -> Self {
-        match value.to_ascii_uppercase().as_bytes() {
-            b"X" => Self::X,
-            b"Y" => Self::Y,
-            _ => panic!("found {} only support N or S in SiteOrient", value),
+/// One or more of `X`, `Y` and `R90`.
+fn read_site_symmetry(reader: &mut CommonReader) -> anyhow::Result<Symmetry> {
+    let mut res = Symmetry::NONE;
+    let token = reader.next_bytes("site symmetry")?;
+    if is_legacy_number(token) {
+        res.y = true;
+        return Ok(res);
+    }
+    if !res.add_token(token) {
+        let found = token.to_vec();
+        return Err(reader
+            .error("site symmetry (X, Y or R90)", Some(&found))
+            .into());
+    }
+    while let Some(token) = reader.peek_token()? {
+        if !res.add_token(token) {
+            break;
         }
+        reader.next_token()?;
     }
+    Ok(res)
 }
+
+/// A run of sites in a row, starting at `origin` along the row.
 #[derive(Default, Clone, Debug)]
-pub struct Row {
-    pub coordinate: i64,
-    pub height: i64,
-    pub site_width: i64,
-    pub site_spacing: i64,
-    pub site_orient: SiteOrient,
-    pub site_symmetry: SiteSymmetry,
-    pub subrow_origin: i64,
+pub struct Subrow {
+    pub origin: f64,
     pub num_sites: i64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Row {
+    /// y of a horizontal row, x of a vertical one.
+    pub coordinate: f64,
+    pub height: f64,
+    pub site_width: f64,
+    pub site_spacing: f64,
+    /// `None` when the file does not say, so the DEF rows may alternate.
+    pub site_orient: Option<Orient>,
+    pub site_symmetry: Symmetry,
+    pub subrows: Vec<Subrow>,
     pub orientation: RowOrientation,
 }
 
+impl Default for Row {
+    fn default() -> Self {
+        Self {
+            coordinate: 0.0,
+            height: 0.0,
+            site_width: 0.0,
+            site_spacing: 0.0,
+            site_orient: None,
+            site_symmetry: Symmetry {
+                y: true,
+                ..Symmetry::NONE
+            },
+            subrows: vec![],
+            orientation: RowOrientation::Horizontal,
+        }
+    }
+}
+
 impl Row {
-    pub async fn read(reader: &mut CommonReader) -> anyhow::Result<Self> {
+    /// Distance between neighbouring sites, falling back to their width.
+    pub fn site_step(&self) -> f64 {
+        if self.site_spacing > 0.0 {
+            self.site_spacing
+        } else {
+            self.site_width
+        }
+    }
+
+    /// The area covered by `subrow`.
+    pub fn subrow_rect(&self, subrow: &Subrow) -> Rect {
+        let length = subrow.num_sites as f64 * self.site_step();
+        let (ll, size) = match self.orientation {
+            RowOrientation::Horizontal => (
+                Point {
+                    x: subrow.origin,
+                    y: self.coordinate,
+                },
+                Point {
+                    x: length,
+                    y: self.height,
+                },
+            ),
+            RowOrientation::Vertical => (
+                Point {
+                    x: self.coordinate,
+                    y: subrow.origin,
+                },
+                Point {
+                    x: self.height,
+                    y: length,
+                },
+            ),
+        };
+        let mut ur = size;
+        ur += ll;
+        Rect { ll, ur }
+    }
+
+    pub fn read(reader: &mut CommonReader) -> anyhow::Result<Self> {
         let mut res = Self::default();
         while let Some(token) = reader.next_token()? {
-            match token.to_ascii_uppercase().as_bytes() {
+            match Keyword::new(token).as_bytes() {
                 b"END" => {
                     break;
                 }
@@ -73,39 +154,51 @@ impl Row {
                     res.orientation = RowOrientation::Vertical;
                 }
                 b"COORDINATE" => {
-                    assert_eq!(b":", reader.next_token().unwrap().unwrap().as_bytes());
-                    res.coordinate = str::parse::<i64>(reader.next_token()?.unwrap())?;
+                    reader.expect(":")?;
+                    res.coordinate = reader.next_parse::<f64>("row coordinate")?;
                 }
                 b"HEIGHT" => {
-                    assert_eq!(b":", reader.next_token().unwrap().unwrap().as_bytes());
-                    res.height = str::parse::<i64>(reader.next_token()?.unwrap())?;
+                    reader.expect(":")?;
+                    res.height = reader.next_parse::<f64>("row height")?;
                 }
                 b"SITEWIDTH" => {
-                    assert_eq!(b":", reader.next_token().unwrap().unwrap().as_bytes());
-                    res.site_width = str::parse::<i64>(reader.next_token()?.unwrap())?;
+                    reader.expect(":")?;
+                    res.site_width = reader.next_parse::<f64>("site width")?;
                 }
                 b"SITESPACING" => {
-                    assert_eq!(b":", reader.next_token().unwrap().unwrap().as_bytes());
-                    res.site_spacing = str::parse::<i64>(reader.next_token()?.unwrap())?;
+                    reader.expect(":")?;
+                    res.site_spacing = reader.next_parse::<f64>("site spacing")?;
                 }
                 b"SITEORIENT" => {
-                    assert_eq!(b":", reader.next_token().unwrap().unwrap().as_bytes());
-                    res.site_orient = reader.next_token()?.unwrap().into();
+                    reader.expect(":")?;
+                    res.site_orient = read_site_orient(reader)?;
                 }
                 b"SITESYMMETRY" => {
-                    assert_eq!(b":", reader.next_token().unwrap().unwrap().as_bytes());
-                    res.site_symmetry = reader.next_token()?.unwrap().into();
+                    reader.expect(":")?;
+                    res.site_symmetry = read_site_symmetry(reader)?;
                 }
                 b"SUBROWORIGIN" => {
-                    assert_eq!(b":", reader.next_token().unwrap().unwrap().as_bytes());
-                    res.subrow_origin = str::parse::<i64>(reader.next_token()?.unwrap())?;
+                    reader.expect(":")?;
+                    let origin = reader.next_parse::<f64>("subrow origin")?;
+                    res.subrows.push(Subrow {
+                        origin,
+                        num_sites: 0,
+                    });
                 }
                 b"NUMSITES" => {
-                    assert_eq!(b":", reader.next_token().unwrap().unwrap().as_bytes());
-                    res.num_sites = str::parse::<i64>(reader.next_token()?.unwrap())?;
+                    reader.expect(":")?;
+                    let num_sites = reader.next_parse::<i64>("number of sites")?;
+                    match res.subrows.last_mut() {
+                        Some(subrow) => subrow.num_sites = num_sites,
+                        None => res.subrows.push(Subrow {
+                            origin: 0.0,
+                            num_sites,
+                        }),
+                    }
                 }
                 _ => {
-                    println!("Unexpected token");
+                    let found = token.to_vec();
+                    return Err(reader.error("row attribute", Some(&found)).into());
                 }
             }
         }
@@ -116,6 +209,8 @@ impl Row {
 #[derive(Default)]
 pub struct Scl {
     rows: Vec<Row>,
+    /// `NumRows` as declared in the header.
+    pub num_rows: Option<usize>,
 }
 
 impl Scl {
@@ -126,22 +221,152 @@ impl Scl {
     pub fn len(&self) -> usize {
         self.rows.len()
     }
-    pub async fn read_from_file(scl_path: &PathBuf) -> anyhow::Result<Self> {
-        let reader = &mut CommonReader::new_from_path(scl_path);
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Bounding box of all subrows, `None` without any.
+    pub fn bounding_box(&self) -> Option<Rect> {
+        let mut rects = self
+            .rows
+            .iter()
+            .flat_map(|row| row.subrows.iter().map(|subrow| row.subrow_rect(subrow)));
+        let first = rects.next()?;
+        Some(rects.fold(first, |res, x| res.union(&x)))
+    }
+    pub fn read_from_file(
+        scl_path: &Path,
+        diagnostics: &mut Diagnostics,
+    ) -> anyhow::Result<Self> {
+        let reader = &mut CommonReader::new_from_path(scl_path)?;
         let mut res = Scl::default();
+        let mut num_rows_at = String::new();
         while let Some(token) = reader.peek_token()? {
-            match token.to_ascii_uppercase().as_bytes() {
-                b"UCLA" | b"#" | b"NUMROWS" => {
-                    reader.swallow_line()?;
-                }
-                b"COREROW" => {
-                    res.rows.push(Row::read(reader).await?);
+            match Keyword::new(token).as_bytes() {
+                b"NUMROWS" => {
+                    reader.next_token()?;
+                    num_rows_at = reader.location();
+                    res.num_rows = reader.header_count("number of rows", diagnostics)?;
                 }
+                b"COREROW" => match Row::read(reader) {
+                    Ok(row) => res.rows.push(row),
+                    Err(e) => {
+                        diagnostics.recover(e)?;
+                        reader.skip_until("CoreRow")?;
+                    }
+                },
                 _ => {
-                    panic!("Unexpected token: {}", token);
+                    let found = token.to_vec();
+                    diagnostics.recover(reader.error("`CoreRow`", Some(&found)).into())?;
+                    reader.swallow_line()?;
                 }
             }
         }
+        if let Some(declared) = res.num_rows {
+            diagnostics.check_count(&num_rows_at, "NumRows", declared, res.len());
+        }
         Ok(res)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        diagnostics::ParseMode,
+        io::test_util::{after_name, read_fixture},
+    };
+
+    const ROWS: &[u8] = b"UCLA scl 1.0\n\
+            NumRows : 3\n\
+            CoreRow Horizontal\n\
+             Coordinate : 0\n\
+             Height : 12\n\
+             Sitewidth : 1\n\
+             Sitespacing : 1\n\
+             Siteorient : 1\n\
+             Sitesymmetry : 1\n\
+             SubrowOrigin : 0 NumSites : 40\n\
+             SubrowOrigin : 60 NumSites : 40\n\
+            End\n\
+            CoreRow Horizontal\n\
+             Coordinate : 12\n\
+             Height : 12\n\
+             Sitewidth : 1\n\
+             Sitespacing : 1\n\
+             Siteorient : FS\n\
+             Sitesymmetry : X Y R90\n\
+             SubrowOrigin : 0 NumSites : 100\n\
+            End\n\
+            CoreRow Vertical\n\
+             Coordinate : 100\n\
+             Height : 2\n\
+             Sitewidth : 1\n\
+             Sitespacing : 1.5\n\
+             Siteorient : W\n\
+             Sitesymmetry : R90\n\
+             SubrowOrigin : 0 NumSites : 16\n\
+            End";
+
+    fn read() -> Scl {
+        let (scl, messages) =
+            read_fixture("rows.scl", ROWS, ParseMode::Strict, Scl::read_from_file);
+        assert!(messages.is_empty());
+        scl.unwrap()
+    }
+
+    fn corners(rect: Rect) -> (f64, f64, f64, f64) {
+        (rect.ll.x, rect.ll.y, rect.ur.x, rect.ur.y)
+    }
+
+    #[test]
+    fn rows_keep_every_subrow() {
+        let scl = read();
+        let rows: Vec<&Row> = scl.iter().collect();
+        assert_eq!(rows.len(), 3);
+        let origins: Vec<(f64, i64)> = rows[0]
+            .subrows
+            .iter()
+            .map(|x| (x.origin, x.num_sites))
+            .collect();
+        assert_eq!(origins, [(0.0, 40), (60.0, 40)]);
+        assert_eq!(rows[0].site_orient, None);
+        assert_eq!(rows[0].site_symmetry.to_string(), "Y");
+        assert_eq!(
+            corners(rows[0].subrow_rect(&rows[0].subrows[1])),
+            (60.0, 0.0, 100.0, 12.0)
+        );
+        assert_eq!(rows[1].site_orient, Some(Orient::FS));
+        assert_eq!(rows[1].site_symmetry.to_string(), "X Y R90");
+    }
+
+    #[test]
+    fn vertical_rows_run_along_y() {
+        let scl = read();
+        let row = scl.iter().nth(2).unwrap();
+        assert!(matches!(row.orientation, RowOrientation::Vertical));
+        assert_eq!(row.site_step(), 1.5);
+        assert_eq!(
+            corners(row.subrow_rect(&row.subrows[0])),
+            (100.0, 0.0, 102.0, 24.0)
+        );
+        assert_eq!(
+            corners(scl.bounding_box().unwrap()),
+            (0.0, 0.0, 102.0, 24.0)
+        );
+    }
+
+    #[test]
+    fn num_rows_mismatch_is_a_warning() {
+        let text = b"NumRows : 2\nCoreRow Horizontal\n Coordinate : 0\nEnd\n";
+        for mode in [ParseMode::Strict, ParseMode::Lenient] {
+            let (scl, messages) = read_fixture("num_rows.scl", text, mode, Scl::read_from_file);
+            assert_eq!(scl.unwrap().len(), 1);
+            assert_eq!(
+                after_name(&messages, "num_rows.scl"),
+                [":1: NumRows declares 2, but 1 were read"]
+            );
+        }
+    }
+}
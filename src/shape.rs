@@ -1,7 +1,15 @@
-use std::path::PathBuf;
+use std::{
+    collections::{BTreeMap, btree_map::Values},
+    path::Path,
+};
 
-use crate::{geom::Rect, io::reader::CommonReader};
+use crate::{
+    diagnostics::Diagnostics,
+    geom::Rect,
+    io::reader::{CommonReader, Keyword},
+};
 
+/// One rectangle of a rectilinear node, in chip coordinates as placed.
 #[derive(Default)]
 pub struct Shape {
     pub shape_name: String,
@@ -9,9 +17,9 @@ pub struct Shape {
 }
 
 impl Shape {
-    pub async fn read(reader: &mut CommonReader) -> anyhow::Result<Self> {
-        let name = reader.next_token()?.unwrap().to_string();
-        let shape = Rect::read_by_lowerleft_width_height(reader).await?;
+    pub fn read(reader: &mut CommonReader) -> anyhow::Result<Self> {
+        let name = reader.next_string("shape name")?;
+        let shape = Rect::read_by_lowerleft_width_height(reader)?;
         Ok(Self {
             shape_name: name,
             rect: shape,
@@ -26,40 +34,118 @@ pub struct NodeShape {
 }
 
 impl NodeShape {
-    pub async fn read(reader: &mut CommonReader) -> anyhow::Result<Self> {
-        let mut res = NodeShape::default();
-        res.node_name = reader.next_token()?.unwrap().to_string();
-        reader.expect(":")?;
-        let num_shape = crate::util::parse(reader.next_token()?.unwrap());
-        for _ in 0..num_shape {
-            res.shape.push(Shape::read(reader).await.unwrap());
+    pub fn node_name(&self) -> &str {
+        &self.node_name
+    }
+
+    pub fn shapes(&self) -> &[Shape] {
+        &self.shape
+    }
+
+    pub fn rects(&self) -> impl Iterator<Item = &Rect> {
+        self.shape.iter().map(|x| &x.rect)
+    }
+
+    /// `<node> : <count>`, then one shape per line. A header in place of a
+    /// shape is left in the stream for the next record.
+    pub fn read(reader: &mut CommonReader) -> anyhow::Result<Self> {
+        let (node_name, num_shape) = reader.read_line(|reader| {
+            let node_name = reader.next_string("node name")?;
+            reader.expect(":")?;
+            Ok((node_name, reader.next_parse::<usize>("number of shapes")?))
+        })?;
+        let mut res = NodeShape {
+            node_name,
+            ..Default::default()
+        };
+        for id in 0..num_shape {
+            if reader.peek_second_is(":")? {
+                let found = reader.peek_token()?.map(<[u8]>::to_vec);
+                let expected = format!("{} more shape(s) of {}", num_shape - id, res.node_name);
+                return Err(reader.error(expected, found.as_deref()).into());
+            }
+            res.shape.push(reader.read_line(Shape::read)?);
         }
         Ok(res)
     }
 }
 
+/// Skip what is left of a malformed record: lines up to the next
+/// `<node> : <count>` header, or header keyword.
+fn skip_to_header(reader: &mut CommonReader) -> anyhow::Result<()> {
+    while reader.peek_token()?.is_some() && !reader.peek_second_is(":")? {
+        reader.swallow_line()?;
+    }
+    Ok(())
+}
+
 #[derive(Default)]
 pub struct Shapes {
-    shapes: Vec<NodeShape>,
+    shapes: BTreeMap<String, NodeShape>,
 }
 
 impl Shapes {
+    pub fn iter(&self) -> Values<'_, String, NodeShape> {
+        self.shapes.values()
+    }
+
+    pub fn get(&self, node_name: &str) -> Option<&NodeShape> {
+        self.shapes.get(node_name)
+    }
+
     pub fn len(&self) -> usize {
         self.shapes.len()
     }
-    pub async fn read_from_file(path: &PathBuf) -> anyhow::Result<Self> {
+    pub fn is_empty(&self) -> bool {
+        self.shapes.is_empty()
+    }
+    pub fn read_from_file(path: &Path, diagnostics: &mut Diagnostics) -> anyhow::Result<Self> {
         let mut res = Self::default();
-        let mut reader = CommonReader::new_from_path(path);
+        let mut reader = CommonReader::new_from_path(path)?;
         while let Some(token) = reader.peek_token()? {
-            match token.to_ascii_uppercase().as_bytes() {
-                b"SHAPES" | b"#" | b"NUMNONRECTANGULARNODES" => {
+            match Keyword::new(token).as_bytes() {
+                b"SHAPES" | b"NUMNONRECTANGULARNODES" => {
                     reader.swallow_line()?;
                 }
-                _ => {
-                    res.shapes.push(NodeShape::read(&mut reader).await?);
-                }
+                _ => match NodeShape::read(&mut reader) {
+                    Ok(node_shape) => {
+                        res.shapes.insert(node_shape.node_name.clone(), node_shape);
+                    }
+                    Err(e) => {
+                        diagnostics.recover(e)?;
+                        skip_to_header(&mut reader)?;
+                    }
+                },
             }
         }
         Ok(res)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{diagnostics::ParseMode, io::test_util::read_fixture};
+
+    #[test]
+    fn malformed_records_skip_to_the_next_header() {
+        let (shapes, messages) = read_fixture(
+            "broken.shapes",
+            b"shapes 1.0\nNumNonRectangularNodes : 4\n\
+            a : x\nShape_0 0 0 1 1\nShape_1 1 0 1 1\n\
+            b : 3\nShape_0 0 0 1 1\n\
+            c : 2\nShape_0 0 0 1\nShape_1 1 0 1 1\n\
+            d : 2\nShape_0 0 0 2 1\nShape_1 0 1 1 1\n",
+            ParseMode::Lenient,
+            Shapes::read_from_file,
+        );
+        let shapes = shapes.unwrap();
+        let names: Vec<&str> = shapes.iter().map(|x| x.node_name()).collect();
+        assert_eq!(names, ["d"]);
+        assert_eq!(shapes.get("d").unwrap().shapes().len(), 2);
+        assert_eq!(messages.len(), 3);
+        assert!(messages[0].ends_with(":3:5: expected number of shapes, found `x`"));
+        assert!(messages[1].ends_with(":8:1: expected 2 more shape(s) of b, found `c`"));
+        assert!(messages[2].ends_with(":9:13: expected y coordinate, found end of line"));
+    }
+}
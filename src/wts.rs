@@ -1 +1,108 @@
-// Unimplemented yet as no weight data in ISPD11
+use std::{collections::BTreeMap, path::Path};
+
+use crate::{diagnostics::Diagnostics, io::reader::CommonReader};
+
+/// Weights from a `.wts` file, one `<name> <weight>` per line. A name may be
+/// a net or a node; it is looked up wherever a weight is needed.
+#[derive(Default)]
+pub struct Wts {
+    weights: BTreeMap<String, f64>,
+}
+
+fn read_weight(reader: &mut CommonReader) -> anyhow::Result<(String, f64)> {
+    let name = reader.next_string("net or node name")?;
+    let weight = reader.read_line(|reader| reader.next_parse::<f64>("weight"))?;
+    Ok((name, weight))
+}
+
+fn to_def(weight: f64) -> i64 {
+    (weight.round() as i64).max(1)
+}
+
+impl Wts {
+    pub fn iter(&self) -> std::collections::btree_map::Iter<'_, String, f64> {
+        self.weights.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.weights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weights.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.weights.get(name).copied()
+    }
+
+    /// DEF `+ WEIGHT` takes a positive integer, so weights are rounded and
+    /// raised to at least 1.
+    pub fn get_for_def(&self, name: &str) -> Option<i64> {
+        self.get(name).map(to_def)
+    }
+
+    /// Weights [`Self::get_for_def`] changes, with what DEF gets instead.
+    pub fn changed_for_def(&self) -> impl Iterator<Item = (&str, f64, i64)> {
+        self.weights
+            .iter()
+            .map(|(name, weight)| (name.as_str(), *weight, to_def(*weight)))
+            .filter(|(_, weight, def)| *def as f64 != *weight)
+    }
+
+    pub fn read_from_file(file_path: &Path, diagnostics: &mut Diagnostics) -> anyhow::Result<Self> {
+        let mut res = Self::default();
+        let mut reader = CommonReader::new_from_path(file_path)?;
+        while reader.peek_token()?.is_some() {
+            match read_weight(&mut reader) {
+                Ok((name, weight)) => {
+                    res.weights.insert(name, weight);
+                }
+                Err(e) => {
+                    diagnostics.recover(e)?;
+                    reader.swallow_line()?;
+                }
+            }
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{diagnostics::ParseMode, io::test_util::read_fixture};
+
+    #[test]
+    fn def_weights_are_rounded_and_positive() {
+        let (wts, _) = read_fixture(
+            "round.wts",
+            b"UCLA wts 1.0\na 2\nb 2.4\nc 0.2\nd -3\ne 2.5\n",
+            ParseMode::Strict,
+            Wts::read_from_file,
+        );
+        let wts = wts.unwrap();
+        let def: Vec<Option<i64>> = ["a", "b", "c", "d", "e", "f"]
+            .iter()
+            .map(|name| wts.get_for_def(name))
+            .collect();
+        assert_eq!(def, [Some(2), Some(2), Some(1), Some(1), Some(3), None]);
+        let changed: Vec<&str> = wts.changed_for_def().map(|(name, _, _)| name).collect();
+        assert_eq!(changed, ["b", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn truncated_weights_do_not_take_the_next_line() {
+        let (wts, messages) = read_fixture(
+            "truncated.wts",
+            b"UCLA wts 1.0\na\nb 2\nc 3\n",
+            ParseMode::Lenient,
+            Wts::read_from_file,
+        );
+        let wts = wts.unwrap();
+        let names: Vec<&str> = wts.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["b", "c"]);
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].ends_with(":2:1: expected weight, found end of line"));
+    }
+}